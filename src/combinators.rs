@@ -0,0 +1,24 @@
+/// Calls `f` with `x`.
+pub fn apply<A, B>(x: A, f: impl FnOnce(A) -> B) -> B {
+    f(x)
+}
+
+/// Returns a function running `f` and then `g` on its result.
+pub fn compose<A, B, C>(f: impl Fn(A) -> B, g: impl Fn(B) -> C) -> impl Fn(A) -> C {
+    move |x| g(f(x))
+}
+
+/// Runs `x` through `f` then `g`, the one-shot form of [`compose`].
+pub fn pipe2<A, B, C>(x: A, f: impl FnOnce(A) -> B, g: impl FnOnce(B) -> C) -> C {
+    g(f(x))
+}
+
+/// Runs `x` through `f`, `g` then `h`.
+pub fn pipe3<A, B, C, D>(
+    x: A,
+    f: impl FnOnce(A) -> B,
+    g: impl FnOnce(B) -> C,
+    h: impl FnOnce(C) -> D,
+) -> D {
+    h(g(f(x)))
+}
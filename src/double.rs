@@ -0,0 +1,40 @@
+use std::{error::Error, fmt, num::ParseIntError};
+
+/// Error returned by [`double_first`].
+#[derive(Debug, PartialEq)]
+pub enum DoubleError {
+    /// There was no first element.
+    Empty,
+    /// The first element isn't an `i32`.
+    Parse(ParseIntError),
+}
+
+impl fmt::Display for DoubleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DoubleError::Empty => write!(f, "please use a vector with at least one element"),
+            DoubleError::Parse(e) => write!(f, "invalid first item to double: {}", e),
+        }
+    }
+}
+
+impl Error for DoubleError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            DoubleError::Parse(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<ParseIntError> for DoubleError {
+    fn from(err: ParseIntError) -> Self {
+        DoubleError::Parse(err)
+    }
+}
+
+/// Parses the first element and doubles it.
+pub fn double_first(vec: &[&str]) -> Result<i32, DoubleError> {
+    let first = vec.first().ok_or(DoubleError::Empty)?;
+    Ok(2 * first.parse::<i32>()?)
+}
@@ -0,0 +1,32 @@
+/// Evaluates each `eval` expression as a `usize` and prints the result.
+#[macro_export]
+macro_rules! calculate {
+    // The pattern for a single `eval`
+    (eval $e:expr) => {{
+        {
+            let val: usize = $e;
+            println!("{} = {}", stringify!($e), val);
+        }
+    }};
+    // Decompose multiple `eval`s recursively
+    (eval $e:expr, $(eval $es:expr),+) => {{
+        $crate::calculate! { eval $e }
+        $crate::calculate! { $(eval $es),+ }
+    }};
+}
+
+/// Like `calculate!` but returns the value instead of printing it, a single
+/// expression gives a `usize` and several `eval`s give a `Vec<usize>`.
+#[macro_export]
+macro_rules! calc {
+    (eval $e:expr) => {{
+        let val: usize = $e;
+        val
+    }};
+    (eval $e:expr, $(eval $es:expr),+) => {
+        vec![$crate::calc!(eval $e), $($crate::calc!(eval $es)),+]
+    };
+    ($e:expr) => {
+        $crate::calc!(eval $e)
+    };
+}
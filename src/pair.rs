@@ -0,0 +1,52 @@
+use std::{error::Error, fmt, str::FromStr};
+
+/// Error returned by [`parse_pair`].
+#[derive(Debug, PartialEq)]
+pub enum PairParseError<EA, EB> {
+    /// The input didn't contain exactly one separator.
+    WrongArity,
+    /// The first half failed to parse.
+    First(EA),
+    /// The second half failed to parse.
+    Second(EB),
+}
+
+impl<EA: fmt::Display, EB: fmt::Display> fmt::Display for PairParseError<EA, EB> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PairParseError::WrongArity => write!(f, "expected exactly two fields"),
+            PairParseError::First(e) => write!(f, "invalid first field: {}", e),
+            PairParseError::Second(e) => write!(f, "invalid second field: {}", e),
+        }
+    }
+}
+
+impl<EA, EB> Error for PairParseError<EA, EB>
+where
+    EA: Error + 'static,
+    EB: Error + 'static,
+{
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            PairParseError::WrongArity => None,
+            PairParseError::First(e) => Some(e),
+            PairParseError::Second(e) => Some(e),
+        }
+    }
+}
+
+/// Splits `s` at the single `sep` and parses both halves,
+/// e.g. `"Mark,20"` into `("Mark", 20)`.
+pub fn parse_pair<A, B>(s: &str, sep: char) -> Result<(A, B), PairParseError<A::Err, B::Err>>
+where
+    A: FromStr,
+    B: FromStr,
+{
+    let (a, b) = s.split_once(sep).ok_or(PairParseError::WrongArity)?;
+    if b.contains(sep) {
+        return Err(PairParseError::WrongArity);
+    }
+    let a = a.parse().map_err(PairParseError::First)?;
+    let b = b.parse().map_err(PairParseError::Second)?;
+    Ok((a, b))
+}
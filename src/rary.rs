@@ -1,6 +1,12 @@
+mod macros;
 mod my;
 use my::nested;
 
+pub mod combinators;
+pub mod double;
+pub mod pair;
+pub mod resource;
+
 pub fn public_function() {
     println!("called rary's `public_function()`");
     nested::function();
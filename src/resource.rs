@@ -0,0 +1,85 @@
+use std::{error::Error, fmt};
+
+type Cleanup = Box<dyn FnOnce() -> Result<(), String>>;
+type LeakHook = Box<dyn FnMut(&str)>;
+
+/// Error from cleaning up one part of a [`Resource`].
+#[derive(Debug, PartialEq)]
+pub struct CloseError {
+    pub part: String,
+    pub reason: String,
+}
+
+impl fmt::Display for CloseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "failed to close {}: {}", self.part, self.reason)
+    }
+}
+
+impl Error for CloseError {}
+
+/// Owns several parts that each need cleanup, like `Pair` owning two boxes.
+///
+/// Parts are cleaned up in reverse order of acquisition, the same order
+/// locals are dropped in. Call `close` to clean up and see errors, dropping
+/// without `close` still cleans up but reports the leak through the hook set
+/// by `on_leak` (a warning on stderr by default).
+pub struct Resource {
+    name: String,
+    parts: Vec<(String, Cleanup)>,
+    on_leak: Option<LeakHook>,
+}
+
+impl Resource {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            parts: Vec::new(),
+            on_leak: None,
+        }
+    }
+
+    pub fn with_part<F>(mut self, name: &str, cleanup: F) -> Self
+    where
+        F: FnOnce() -> Result<(), String> + 'static,
+    {
+        self.parts.push((name.to_string(), Box::new(cleanup)));
+        self
+    }
+
+    pub fn on_leak<F: FnMut(&str) + 'static>(mut self, hook: F) -> Self {
+        self.on_leak = Some(Box::new(hook));
+        self
+    }
+
+    /// Cleans up every part, returning the first error. All parts are
+    /// cleaned up even if an earlier one fails.
+    pub fn close(mut self) -> Result<(), CloseError> {
+        self.cleanup()
+    }
+
+    fn cleanup(&mut self) -> Result<(), CloseError> {
+        let mut result = Ok(());
+        while let Some((part, cleanup)) = self.parts.pop() {
+            if let Err(reason) = cleanup() {
+                if result.is_ok() {
+                    result = Err(CloseError { part, reason });
+                }
+            }
+        }
+        result
+    }
+}
+
+impl Drop for Resource {
+    fn drop(&mut self) {
+        if self.parts.is_empty() {
+            return;
+        }
+        match self.on_leak.as_mut() {
+            Some(hook) => hook(&self.name),
+            None => eprintln!("warning: {} dropped without close", self.name),
+        }
+        let _ = self.cleanup();
+    }
+}
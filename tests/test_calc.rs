@@ -0,0 +1,15 @@
+///
+/// `calc!` is the value-returning sibling of `calculate!`, see `src/macros.rs`.
+///
+use rary::calc;
+
+#[test]
+fn test_calc() {
+    assert_eq!(calc!(1 + 2), 3);
+    assert_eq!(calc!(eval(1 + 2) * (8 / 4)), 6);
+    assert_eq!(
+        calc!(eval 1 + 2, eval 3 + 4, eval (2 * 3) + 1),
+        vec![3, 7, 7]
+    );
+    assert_eq!(calc!(2 * 3) + 1, 7);
+}
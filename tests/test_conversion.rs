@@ -185,6 +185,37 @@ mod tests {
     }
 }
 
+#[cfg(test)]
+mod tests_parse_pair {
+    use rary::pair::{parse_pair, PairParseError};
+
+    #[test]
+    fn test_good_pair() {
+        let p: (String, u32) = parse_pair("Mark,20", ',').unwrap();
+        assert_eq!(p, ("Mark".to_string(), 20));
+    }
+
+    #[test]
+    fn test_missing_separator() {
+        let p = parse_pair::<String, u32>("Mark", ',');
+        assert_eq!(p, Err(PairParseError::WrongArity));
+        let p = parse_pair::<String, u32>("Mike,32,man", ',');
+        assert_eq!(p, Err(PairParseError::WrongArity));
+    }
+
+    #[test]
+    fn test_bad_second_field() {
+        let p = parse_pair::<String, u32>("Mark,twenty", ',');
+        assert!(matches!(p, Err(PairParseError::Second(_))));
+        assert_eq!(
+            p.unwrap_err().to_string(),
+            "invalid second field: invalid digit found in string"
+        );
+        let p = parse_pair::<u32, u32>("one,1", ',');
+        assert!(matches!(p, Err(PairParseError::First(_))));
+    }
+}
+
 use std::convert::AsRef;
 
 // Obtain the number of bytes (not characters) in the given argument
@@ -460,6 +460,41 @@ fn test_multiple_error_types() {
     println!("6 The first doubled is {:?}", double_first6(&strings));
 }
 
+/// A cleaned-up `double_first` with a typed error lives in `src/double.rs`.
+#[cfg(test)]
+mod tests_double_first {
+    use rary::double::{double_first, DoubleError};
+    use std::error::Error;
+
+    #[test]
+    fn test_empty() {
+        let err = double_first(&[]).unwrap_err();
+        assert_eq!(err, DoubleError::Empty);
+        assert_eq!(
+            err.to_string(),
+            "please use a vector with at least one element"
+        );
+        assert!(err.source().is_none());
+    }
+
+    #[test]
+    fn test_not_a_number() {
+        let err = double_first(&["tofu", "93"]).unwrap_err();
+        assert!(matches!(err, DoubleError::Parse(_)));
+        assert_eq!(
+            err.to_string(),
+            "invalid first item to double: invalid digit found in string"
+        );
+        assert!(err.source().is_some());
+    }
+
+    #[test]
+    fn test_valid() {
+        assert_eq!(double_first(&["42", "tofu"]), Ok(84));
+        assert_eq!(double_first(&["-7"]), Ok(-14));
+    }
+}
+
 /// ## Iterating over Results
 ///
 /// An `Iter::map` operation might fail, let's step through strategies for handing this.
@@ -73,6 +73,59 @@ fn test_methods() {
     pair.destroy();
 }
 
+// `rary::resource::Resource` generalizes `Pair::destroy`: `close(self)` reports cleanup
+// errors, and `Drop` still cleans up but warns that `close` was never called.
+mod resource {
+    use rary::resource::{CloseError, Resource};
+    use std::{cell::RefCell, rc::Rc};
+
+    fn resource(log: &Rc<RefCell<Vec<String>>>) -> Resource {
+        let (first, second, leaks) = (log.clone(), log.clone(), log.clone());
+        Resource::new("pair")
+            .with_part("first", move || {
+                first.borrow_mut().push("first".into());
+                Ok(())
+            })
+            .with_part("second", move || {
+                second.borrow_mut().push("second".into());
+                Ok(())
+            })
+            .on_leak(move |name| leaks.borrow_mut().push(format!("leaked {}", name)))
+    }
+
+    #[test]
+    fn test_close() {
+        let log = Rc::new(RefCell::new(vec![]));
+        resource(&log).close().unwrap();
+        assert_eq!(*log.borrow(), ["second", "first"]);
+    }
+
+    #[test]
+    fn test_drop_without_close() {
+        let log = Rc::new(RefCell::new(vec![]));
+        {
+            let _r = resource(&log);
+        }
+        assert_eq!(*log.borrow(), ["leaked pair", "second", "first"]);
+    }
+
+    #[test]
+    fn test_close_error() {
+        let r = Resource::new("files")
+            .with_part("a", || Err("disk full".into()))
+            .with_part("b", || Ok(()));
+        let err = r.close().unwrap_err();
+        assert_eq!(
+            err,
+            CloseError {
+                part: "a".into(),
+                reason: "disk full".into()
+            }
+        );
+        assert_eq!(err.to_string(), "failed to close a: disk full");
+    }
+}
+
 ///
 /// # Closures
 ///
@@ -173,3 +226,30 @@ fn test_closures() {
     let fn_once = create_fnonce();
     fn_once();
 }
+
+mod combinators {
+    use rary::combinators::{apply, compose, pipe2, pipe3};
+
+    #[test]
+    fn test_compose() {
+        let inc_then_double = compose(|x: i32| x + 1, |x| x * 2);
+        assert_eq!(inc_then_double(1), 4);
+        assert_eq!(inc_then_double(5), 12);
+
+        let double_then_inc = compose(|x: i32| x * 2, |x| x + 1);
+        assert_eq!(double_then_inc(5), 11);
+
+        let describe = compose(inc_then_double, |x| format!("got {}", x));
+        assert_eq!(describe(0), "got 2");
+    }
+
+    #[test]
+    fn test_pipe() {
+        assert_eq!(apply(3, |x| x * 2), 6);
+        assert_eq!(pipe2(1, |x| x + 1, |x| x * 2), 4);
+        assert_eq!(
+            pipe3("a,b", |s: &str| s.split(','), Iterator::count, |n| n * 10),
+            20
+        );
+    }
+}
@@ -184,29 +184,7 @@ mod test {
     test!(sub_assign, 3u32, 2u32, 1u32);
 }
 
-///
-/// ## DSLs
-///
-/// A DSL is a mini "language" embedded in a Rust macro
-///
-/// ## Variadic Interfaces
-///
-/// A variadic interface takes an arbitrary number of arguments.
-///
-macro_rules! calculate {
-    // The pattern for a single `eval`
-    (eval $e: expr) => {{
-        {
-            let val:usize = $e;
-            println!("{} = {}", stringify!($e), val);
-        }
-    }};
-    // Decompose multiple `eval`s recursively
-    (eval $e:expr, $(eval $es:expr),+) => {{
-        calculate! { eval $e }
-        calculate! { $(eval $es),+ }
-    }};
-}
+use rary::calculate;
 
 #[test]
 fn test_dsl() {
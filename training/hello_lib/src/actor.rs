@@ -2,12 +2,128 @@
 // ActorMessage
 // HandleCall
 // Pid
-use anyhow::Result;
-use tokio::sync::{mpsc, oneshot};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use futures::future::{self, BoxFuture};
+use std::{
+    any::{self, Any},
+    fmt,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+#[cfg(feature = "metrics")]
+use std::{sync::atomic::AtomicU64, time::Instant};
+use tokio::{
+    sync::{
+        mpsc::{self, error::TrySendError},
+        oneshot, watch, Notify,
+    },
+    time::timeout,
+};
+use tracing::{debug, error, field, info_span, warn, Instrument};
+
+use crate::registry::Registry;
+
+// runs against the actor's state, see `Pid::inspect`
+type Inspection = Box<dyn FnOnce(&dyn Any) + Send>;
+
+// inspections waiting for the actor, `Pid::inspect` waits for room beyond that
+const MAX_INSPECTIONS: usize = 16;
 
 pub struct Actor<State, Request, Reply> {
-    receiver: mpsc::Receiver<ActorMessage<Request, Reply>>,
+    receiver: Inbox<ActorMessage<Request, Reply>>,
     state: State,
+    // kept apart from the mailbox, so `Request` needs no variant for it
+    inspections: mpsc::Receiver<Inspection>,
+    // notified by `Pid::stop`
+    stop: Arc<Notify>,
+    // dropped with the actor's task, see `Pid::stop_and_wait`
+    _done: watch::Sender<()>,
+    #[cfg(feature = "metrics")]
+    metrics: Arc<Metrics>,
+}
+
+impl<State: 'static, Request, Reply> Actor<State, Request, Reply> {
+    fn new(max_msg_len: Option<usize>, state: State) -> (Self, Pid<Request, Reply>) {
+        let (sender, receiver) = mailbox(max_msg_len);
+        let (inspect, inspections) = mpsc::channel(MAX_INSPECTIONS);
+        let stop = Arc::new(Notify::new());
+        let (done_tx, done) = watch::channel(());
+        #[cfg(feature = "metrics")]
+        let metrics = Arc::new(Metrics::default());
+        let actor = Self {
+            receiver,
+            state,
+            inspections,
+            stop: stop.clone(),
+            _done: done_tx,
+            #[cfg(feature = "metrics")]
+            metrics: metrics.clone(),
+        };
+        let pid = Pid {
+            sender,
+            inspect,
+            stop,
+            done,
+            #[cfg(feature = "metrics")]
+            metrics,
+        };
+        (actor, pid)
+    }
+
+    // the next message, `None` once the actor has stopped and handled what
+    // was already in its mailbox. Inspections are run while waiting
+    async fn recv(&mut self) -> Option<ActorMessage<Request, Reply>> {
+        loop {
+            tokio::select! {
+                biased;
+                _ = self.stop.notified() => self.receiver.close(),
+                msg = self.receiver.recv() => return msg,
+                Some(inspect) = self.inspections.recv() => inspect(&self.state),
+            }
+        }
+    }
+}
+
+impl<State, Request, Reply> Actor<State, Request, Reply>
+where
+    State: Send + 'static,
+    Request: Send + 'static,
+    Reply: Send + 'static,
+{
+    // the receive loop of every kind of actor, `handle` starts the call
+    // that turns a request into its reply
+    fn run<H>(mut self, mut handle: H)
+    where
+        H: for<'a> FnMut(&'a mut State, &'a Request) -> BoxFuture<'a, Result<Reply>>,
+        H: Send + 'static,
+    {
+        tokio::spawn(async move {
+            while let Some(msg) = self.recv().await {
+                if msg.is_cancelled() {
+                    debug!("request cancelled by caller, skipped");
+                    continue;
+                }
+                let span = info_span!("handle_call", status = field::Empty);
+                #[cfg(feature = "metrics")]
+                let start = Instant::now();
+                // a synchronous handler does all its work while starting the
+                // call, an async one while being polled. Entering the span
+                // across the await would leak it into other tasks
+                let call = span.in_scope(|| handle(&mut self.state, &msg.data));
+                let reply = call.instrument(span.clone()).await;
+                #[cfg(feature = "metrics")]
+                self.metrics.record(start.elapsed());
+                let status = if reply.is_ok() { "ok" } else { "err" };
+                span.record("status", &status);
+                msg.reply(reply);
+            }
+        });
+    }
 }
 
 impl<State, Request, Reply> Actor<State, Request, Reply>
@@ -16,42 +132,452 @@ where
     Request: Send + 'static,
     Reply: Send + 'static,
 {
+    /// Spawns the actor with a mailbox of `max_msg_len` messages, sends to
+    /// a full mailbox wait for room.
     pub fn spawn(max_msg_len: usize, state: State) -> Result<Pid<Request, Reply>> {
-        let (sender, receiver) = mpsc::channel(max_msg_len);
+        Self::start(Some(max_msg_len), state, None)
+    }
 
-        let mut actor = Self {
-            receiver,
-            state: state,
-        };
+    /// Spawns the actor with a mailbox that never fills up, sends never
+    /// wait but nothing holds back a caller outpacing the actor.
+    pub fn spawn_unbounded(state: State) -> Result<Pid<Request, Reply>> {
+        Self::start(None, state, None)
+    }
 
-        tokio::spawn(async move {
-            while let Some(msg) = actor.receiver.recv().await {
-                let state = &mut actor.state;
-                let reply = state.handle_call(&msg.data).unwrap();
-                let _ = msg.sender.send(reply);
-            }
+    /// Like `spawn`, but a panic in `handle_call` restarts the actor with a
+    /// fresh `State::default()` instead of killing it. The caller whose
+    /// request panicked gets an error.
+    pub fn spawn_supervised(max_msg_len: usize, state: State) -> Result<Pid<Request, Reply>>
+    where
+        State: Default,
+    {
+        Self::start(Some(max_msg_len), state, Some(State::default))
+    }
+
+    // `restart` makes the actor catch panics and carry on with its state
+    fn start(
+        max_msg_len: Option<usize>,
+        state: State,
+        restart: Option<fn() -> State>,
+    ) -> Result<Pid<Request, Reply>> {
+        let (actor, pid) = Self::new(max_msg_len, state);
+        actor.run(move |state, request| {
+            let reply = match restart {
+                None => state.handle_call(request),
+                Some(fresh) => {
+                    let call = AssertUnwindSafe(|| state.handle_call(request));
+                    panic::catch_unwind(call).unwrap_or_else(|_| {
+                        error!("handle_call panicked, restarting the actor");
+                        *state = fresh();
+                        Err(anyhow!("actor panicked and was restarted"))
+                    })
+                }
+            };
+            Box::pin(future::ready(reply))
         });
+        Ok(pid)
+    }
+
+    /// Spawns the actor and registers its `Pid` under `name`.
+    pub fn spawn_named(
+        registry: &Registry,
+        name: impl Into<String>,
+        max_msg_len: usize,
+        state: State,
+    ) -> Result<Pid<Request, Reply>> {
+        let pid = Self::spawn(max_msg_len, state)?;
+        registry.register(name, pid.clone());
+        Ok(pid)
+    }
+}
+
+impl<State, Request, Reply> Actor<State, Request, Reply>
+where
+    State: AsyncHandleCall<Request = Request, Reply = Reply> + Send + 'static,
+    Request: Send + Sync + 'static,
+    Reply: Send + 'static,
+{
+    /// Spawns an actor whose `handle_call` can await, messages are still
+    /// handled one at a time.
+    pub fn spawn_async(max_msg_len: usize, state: State) -> Result<Pid<Request, Reply>> {
+        let (actor, pid) = Self::new(Some(max_msg_len), state);
+        actor.run(|state, request| state.handle_call(request));
+        Ok(pid)
+    }
+}
+
+// an actor's mailbox, bounded by its length or not at all
+fn mailbox<T>(max_len: Option<usize>) -> (Mailbox<T>, Inbox<T>) {
+    match max_len {
+        Some(max_len) => {
+            let (sender, receiver) = mpsc::channel(max_len);
+            (
+                Mailbox::Bounded { sender, max_len },
+                Inbox::Bounded(receiver),
+            )
+        }
+        None => {
+            let (sender, receiver) = mpsc::unbounded_channel();
+            let len = Arc::new(AtomicUsize::new(0));
+            let inbox = Inbox::Unbounded(receiver, len.clone());
+            (Mailbox::Unbounded { sender, len }, inbox)
+        }
+    }
+}
+
+// the sending half of a mailbox, held by the `Pid`s
+enum Mailbox<T> {
+    Bounded {
+        sender: mpsc::Sender<T>,
+        max_len: usize,
+    },
+    // the channel doesn't count queued messages, `len` does
+    Unbounded {
+        sender: mpsc::UnboundedSender<T>,
+        len: Arc<AtomicUsize>,
+    },
+}
+
+impl<T> Mailbox<T> {
+    // gives the message back if the actor is gone
+    async fn send(&self, msg: T) -> Result<(), T> {
+        match self {
+            Mailbox::Bounded { sender, .. } => sender.send(msg).await.map_err(|e| e.0),
+            Mailbox::Unbounded { .. } => self.try_send(msg).map_err(|e| match e {
+                TrySendError::Full(msg) | TrySendError::Closed(msg) => msg,
+            }),
+        }
+    }
+
+    fn try_send(&self, msg: T) -> Result<(), TrySendError<T>> {
+        match self {
+            Mailbox::Bounded { sender, .. } => sender.try_send(msg),
+            Mailbox::Unbounded { sender, len } => {
+                // counted first, the actor may take it off right away
+                len.fetch_add(1, Ordering::Relaxed);
+                sender.send(msg).map_err(|e| {
+                    len.fetch_sub(1, Ordering::Relaxed);
+                    TrySendError::Closed(e.0)
+                })
+            }
+        }
+    }
+
+    fn capacity(&self) -> usize {
+        match self {
+            Mailbox::Bounded { sender, .. } => sender.capacity(),
+            Mailbox::Unbounded { .. } => usize::MAX,
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Mailbox::Bounded { sender, max_len } => max_len - sender.capacity(),
+            Mailbox::Unbounded { len, .. } => len.load(Ordering::Relaxed),
+        }
+    }
+}
+
+// derived Clone and Debug would require T to be Clone and Debug
+impl<T> Clone for Mailbox<T> {
+    fn clone(&self) -> Self {
+        match self {
+            Mailbox::Bounded { sender, max_len } => Mailbox::Bounded {
+                sender: sender.clone(),
+                max_len: *max_len,
+            },
+            Mailbox::Unbounded { sender, len } => Mailbox::Unbounded {
+                sender: sender.clone(),
+                len: len.clone(),
+            },
+        }
+    }
+}
+
+impl<T> fmt::Debug for Mailbox<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Mailbox::Bounded { max_len, .. } => f
+                .debug_struct("Bounded")
+                .field("len", &self.len())
+                .field("max_len", max_len)
+                .finish(),
+            Mailbox::Unbounded { .. } => f
+                .debug_struct("Unbounded")
+                .field("len", &self.len())
+                .finish(),
+        }
+    }
+}
+
+// the receiving half of a mailbox, owned by the actor
+enum Inbox<T> {
+    Bounded(mpsc::Receiver<T>),
+    Unbounded(mpsc::UnboundedReceiver<T>, Arc<AtomicUsize>),
+}
+
+impl<T> Inbox<T> {
+    async fn recv(&mut self) -> Option<T> {
+        match self {
+            Inbox::Bounded(receiver) => receiver.recv().await,
+            Inbox::Unbounded(receiver, len) => {
+                let msg = receiver.recv().await?;
+                len.fetch_sub(1, Ordering::Relaxed);
+                Some(msg)
+            }
+        }
+    }
 
-        Ok(Pid { sender })
+    // refuses new messages, those already queued are still received
+    fn close(&mut self) {
+        match self {
+            Inbox::Bounded(receiver) => receiver.close(),
+            Inbox::Unbounded(receiver, _) => receiver.close(),
+        }
     }
 }
 
 struct ActorMessage<Request, Reply> {
-    sender: oneshot::Sender<Reply>,
+    // `None` for a cast, nobody waits for the reply
+    sender: Option<oneshot::Sender<Result<Reply>>>,
     data: Request,
 }
 
-#[derive(Debug, Clone)]
+impl<Request, Reply> ActorMessage<Request, Reply> {
+    // the caller gave up waiting, don't do work nobody will see
+    fn is_cancelled(&self) -> bool {
+        self.sender.as_ref().is_some_and(|s| s.is_closed())
+    }
+
+    // an error goes back to the caller, the actor keeps serving
+    fn reply(self, reply: Result<Reply>) {
+        match self.sender {
+            Some(sender) => {
+                let _ = sender.send(reply);
+            }
+            None => {
+                if let Err(e) = reply {
+                    warn!("cast failed: {}", e);
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
 pub struct Pid<Request, Reply> {
-    sender: mpsc::Sender<ActorMessage<Request, Reply>>,
+    sender: Mailbox<ActorMessage<Request, Reply>>,
+    inspect: mpsc::Sender<Inspection>,
+    stop: Arc<Notify>,
+    // closed once the actor's task has ended
+    done: watch::Receiver<()>,
+    #[cfg(feature = "metrics")]
+    metrics: Arc<Metrics>,
+}
+
+// derived Clone would require Request and Reply to be Clone
+impl<Request, Reply> Clone for Pid<Request, Reply> {
+    fn clone(&self) -> Self {
+        Self {
+            sender: self.sender.clone(),
+            inspect: self.inspect.clone(),
+            stop: self.stop.clone(),
+            done: self.done.clone(),
+            #[cfg(feature = "metrics")]
+            metrics: self.metrics.clone(),
+        }
+    }
 }
 
 impl<Request, Reply> Pid<Request, Reply> {
+    #[cfg(feature = "metrics")]
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    /// Returns the actor's reply, or the error its `handle_call` returned.
+    /// Fails with `ActorError::Closed` if the actor has stopped.
     pub async fn send(&self, data: Request) -> Result<Reply> {
         let (sender, receiver) = oneshot::channel();
-        let msg = ActorMessage { sender, data };
-        let _ = self.sender.send(msg).await;
-        Ok(receiver.await?)
+        let msg = ActorMessage {
+            sender: Some(sender),
+            data,
+        };
+        self.sender
+            .send(msg)
+            .await
+            .map_err(|_| ActorError::Closed)?;
+        receiver.await.map_err(|_| ActorError::Closed)?
+    }
+
+    /// Like `send`, but fails with `ActorError::Timeout` if queueing the
+    /// message and getting the reply take longer than `dur`.
+    pub async fn send_timeout(&self, data: Request, dur: Duration) -> Result<Reply> {
+        timeout(dur, self.send(data))
+            .await
+            .map_err(|_| ActorError::Timeout)?
+    }
+
+    /// Queues `data` if the mailbox has room, failing right away with the
+    /// request given back otherwise. The returned receiver gets the reply,
+    /// await it later or drop it if the reply isn't needed.
+    pub fn try_send(
+        &self,
+        data: Request,
+    ) -> Result<oneshot::Receiver<Result<Reply>>, TrySendError<Request>> {
+        let (sender, receiver) = oneshot::channel();
+        let msg = ActorMessage {
+            sender: Some(sender),
+            data,
+        };
+        match self.sender.try_send(msg) {
+            Ok(()) => Ok(receiver),
+            Err(TrySendError::Full(msg)) => Err(TrySendError::Full(msg.data)),
+            Err(TrySendError::Closed(msg)) => Err(TrySendError::Closed(msg.data)),
+        }
+    }
+
+    /// Number of messages the mailbox can take before sends wait,
+    /// `usize::MAX` for an unbounded one.
+    pub fn capacity(&self) -> usize {
+        self.sender.capacity()
+    }
+
+    /// Number of messages waiting in the mailbox.
+    pub fn len(&self) -> usize {
+        self.sender.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Runs `f` on the actor's state between two messages and returns its
+    /// result, for debugging. Fails with `ActorError::WrongState` if the
+    /// actor's state isn't a `State`, `ActorError::Panicked` if `f` panics
+    /// and `ActorError::Closed` if the actor is gone.
+    pub async fn inspect<State, F, R>(&self, f: F) -> Result<R>
+    where
+        State: 'static,
+        F: FnOnce(&State) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let (sender, receiver) = oneshot::channel();
+        let inspection: Inspection = Box::new(move |state| {
+            let r = match state.downcast_ref() {
+                // a panic fails the caller, the actor keeps serving
+                Some(state) => panic::catch_unwind(AssertUnwindSafe(|| f(state)))
+                    .map_err(|_| ActorError::Panicked),
+                None => Err(ActorError::WrongState(any::type_name::<State>())),
+            };
+            let _ = sender.send(r);
+        });
+        self.inspect
+            .send(inspection)
+            .await
+            .map_err(|_| ActorError::Closed)?;
+        Ok(receiver.await.map_err(|_| ActorError::Closed)??)
+    }
+
+    /// Stops the actor: messages already in its mailbox are still handled,
+    /// sends from any `Pid` fail with `ActorError::Closed` from now on.
+    pub fn stop(self) {
+        self.stop.notify_one();
+    }
+
+    /// Like `stop`, and waits until the actor has handled its last message
+    /// and its task has ended.
+    pub async fn stop_and_wait(mut self) {
+        self.stop.notify_one();
+        // only fails once the sender is gone with the task
+        while self.done.changed().await.is_ok() {}
+    }
+
+    /// Queues `data` without waiting for it to be handled, the reply is
+    /// dropped.
+    pub async fn cast(&self, data: Request) -> Result<()> {
+        let msg = ActorMessage { sender: None, data };
+        self.sender
+            .send(msg)
+            .await
+            .map_err(|_| ActorError::Closed.into())
+    }
+}
+
+/// Sends a clone of `data` to every actor at once, returns their replies in
+/// the order of `pids`. A dead actor only fails its own slot with
+/// `ActorError::Closed`, the others still get the message.
+pub async fn broadcast<Req: Clone, Rep>(pids: &[Pid<Req, Rep>], data: Req) -> Vec<Result<Rep>> {
+    future::join_all(pids.iter().map(|pid| pid.send(data.clone()))).await
+}
+
+/// Why a message sent to an actor got no reply.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ActorError {
+    /// The actor has stopped.
+    Closed,
+    /// No reply within the time given to `send_timeout`.
+    Timeout,
+    /// `Pid::inspect` expected a state of this type.
+    WrongState(&'static str),
+    /// The closure given to `Pid::inspect` panicked.
+    Panicked,
+}
+
+impl fmt::Display for ActorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ActorError::Closed => write!(f, "actor is gone"),
+            ActorError::Timeout => write!(f, "actor didn't reply in time"),
+            ActorError::WrongState(name) => write!(f, "actor state is not a {}", name),
+            ActorError::Panicked => write!(f, "inspection panicked"),
+        }
+    }
+}
+
+impl std::error::Error for ActorError {}
+
+/// Upper bounds of the latency histogram buckets, slower calls land in an
+/// extra overflow bucket.
+#[cfg(feature = "metrics")]
+pub const LATENCY_BUCKETS: [Duration; 4] = [
+    Duration::from_millis(1),
+    Duration::from_millis(10),
+    Duration::from_millis(100),
+    Duration::from_secs(1),
+];
+
+/// Counters shared by an actor and all its `Pid`s.
+#[cfg(feature = "metrics")]
+#[derive(Debug, Default)]
+pub struct Metrics {
+    processed: AtomicU64,
+    latency: [AtomicU64; LATENCY_BUCKETS.len() + 1],
+}
+
+#[cfg(feature = "metrics")]
+impl Metrics {
+    fn record(&self, elapsed: Duration) {
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|b| elapsed < *b)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        self.latency[bucket].fetch_add(1, Ordering::Relaxed);
+        self.processed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Number of messages handled so far.
+    pub fn processed(&self) -> u64 {
+        self.processed.load(Ordering::Relaxed)
+    }
+
+    /// `handle_call` counts per bucket of `LATENCY_BUCKETS`, plus overflow.
+    pub fn latency_histogram(&self) -> [u64; LATENCY_BUCKETS.len() + 1] {
+        let mut counts = [0; LATENCY_BUCKETS.len() + 1];
+        for (count, bucket) in counts.iter_mut().zip(&self.latency) {
+            *count = bucket.load(Ordering::Relaxed);
+        }
+        counts
     }
 }
 
@@ -62,6 +588,15 @@ pub trait HandleCall {
     fn handle_call(&mut self, request: &Self::Request) -> Result<Self::Reply>;
 }
 
+/// `HandleCall` for states that await, e.g. on I/O, see `Actor::spawn_async`.
+#[async_trait]
+pub trait AsyncHandleCall {
+    type Request: Sync;
+    type Reply;
+
+    async fn handle_call(&mut self, request: &Self::Request) -> Result<Self::Reply>;
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -71,18 +606,19 @@ mod tests {
         type Reply = i32;
 
         fn handle_call(&mut self, request: &Self::Request) -> Result<Self::Reply> {
-            match request {
-                &cmd @ "+1" => {
+            match *request {
+                cmd @ "+1" => {
                     *self += 1;
                     println!("recv cmd: {}, state={}", cmd, *self);
                     Ok(*self)
                 }
-                &cmd @ "-1" => {
+                cmd @ "-1" => {
                     *self -= 1;
                     println!("recv cmd: {}, state={}", cmd, *self);
                     Ok(*self)
                 }
-                &_ => unimplemented!(),
+                "get" => Ok(*self),
+                cmd => anyhow::bail!("unknown cmd: {}", cmd),
             }
         }
     }
@@ -99,4 +635,309 @@ mod tests {
         let r3 = p3.send("-1").await.unwrap();
         assert_eq!(r3, 1);
     }
+
+    #[tokio::test]
+    async fn errors_reach_the_caller() {
+        let p: Pid<&str, i32> = Actor::spawn(10, 0i32).unwrap();
+        let err = p.send("*2").await.unwrap_err();
+        assert_eq!(err.to_string(), "unknown cmd: *2");
+        assert_eq!(p.send("+1").await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn casts_update_state() {
+        let p: Pid<&str, i32> = Actor::spawn(10, 0i32).unwrap();
+        for _ in 0..3 {
+            p.cast("+1").await.unwrap();
+        }
+        p.cast("*2").await.unwrap();
+        assert_eq!(p.send("get").await.unwrap(), 3);
+    }
+
+    // sleeps for the requested number of milliseconds, panics on 0
+    struct Sleepy;
+
+    impl HandleCall for Sleepy {
+        type Request = u64;
+        type Reply = ();
+
+        fn handle_call(&mut self, millis: &u64) -> Result<()> {
+            assert!(*millis > 0, "asked to sleep for nothing");
+            std::thread::sleep(Duration::from_millis(*millis));
+            Ok(())
+        }
+    }
+
+    // the actor blocks its thread, the caller needs another one
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn slow_replies_time_out() {
+        let p: Pid<u64, ()> = Actor::spawn(10, Sleepy).unwrap();
+        let err = p
+            .send_timeout(200, Duration::from_millis(20))
+            .await
+            .unwrap_err();
+        assert_eq!(err.downcast_ref(), Some(&ActorError::Timeout));
+        p.send_timeout(1, Duration::from_secs(1)).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn dead_actor_fails_sends() {
+        let p: Pid<u64, ()> = Actor::spawn(10, Sleepy).unwrap();
+        // the panic kills the actor with the message
+        let err = p.send(0).await.unwrap_err();
+        assert_eq!(err.downcast_ref(), Some(&ActorError::Closed));
+        let err = p.send(1).await.unwrap_err();
+        assert_eq!(err.downcast_ref(), Some(&ActorError::Closed));
+    }
+
+    #[derive(Default)]
+    struct Fragile(i32);
+
+    impl HandleCall for Fragile {
+        type Request = &'static str;
+        type Reply = i32;
+
+        fn handle_call(&mut self, request: &Self::Request) -> Result<Self::Reply> {
+            match *request {
+                "+1" => self.0 += 1,
+                cmd => panic!("can't handle {}", cmd),
+            }
+            Ok(self.0)
+        }
+    }
+
+    #[tokio::test]
+    async fn supervised_actors_restart_after_panics() {
+        let p: Pid<&str, i32> = Actor::spawn_supervised(10, Fragile(10)).unwrap();
+        assert_eq!(p.send("+1").await.unwrap(), 11);
+        assert!(p.send("boom").await.is_err());
+        // back to a default state
+        assert_eq!(p.send("+1").await.unwrap(), 1);
+    }
+
+    // replies with the number of requests so far, a bit later
+    struct Delayed(u32);
+
+    #[async_trait]
+    impl AsyncHandleCall for Delayed {
+        type Request = Duration;
+        type Reply = u32;
+
+        async fn handle_call(&mut self, delay: &Duration) -> Result<u32> {
+            tokio::time::sleep(*delay).await;
+            self.0 += 1;
+            Ok(self.0)
+        }
+    }
+
+    #[tokio::test]
+    async fn async_handlers_are_awaited() {
+        let p: Pid<Duration, u32> = Actor::spawn_async(10, Delayed(0)).unwrap();
+        let slow = p.send(Duration::from_millis(20));
+        let fast = p.send(Duration::from_millis(1));
+        // handled in order, the fast one waits for the slow one
+        let (slow, fast) = tokio::join!(slow, fast);
+        assert_eq!((slow.unwrap(), fast.unwrap()), (1, 2));
+    }
+
+    #[tokio::test]
+    async fn try_send_fails_on_a_full_mailbox() {
+        let p: Pid<&str, i32> = Actor::spawn(1, 0i32).unwrap();
+        // the actor doesn't run before this task yields
+        let reply = p.try_send("+1").unwrap();
+        assert_eq!((p.len(), p.capacity()), (1, 0));
+        match p.try_send("+1") {
+            Err(TrySendError::Full(data)) => assert_eq!(data, "+1"),
+            _ => panic!("expected a full mailbox"),
+        }
+        assert_eq!(reply.await.unwrap().unwrap(), 1);
+        assert!(p.is_empty());
+    }
+
+    #[tokio::test]
+    async fn bounded_mailboxes_backpressure() {
+        let p: Pid<&str, i32> = Actor::spawn(1, 0i32).unwrap();
+        // the actor doesn't run before this task yields
+        let reply = p.try_send("+1").unwrap();
+        tokio::select! {
+            biased;
+            _ = p.cast("+1") => panic!("sent to a full mailbox"),
+            _ = async {} => {}
+        }
+        assert_eq!(reply.await.unwrap().unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn unbounded_mailboxes_take_bursts() {
+        let p: Pid<&str, i32> = Actor::spawn_unbounded(0i32).unwrap();
+        let replies: Vec<_> = (0..100).map(|_| p.try_send("+1").unwrap()).collect();
+        assert_eq!((p.len(), p.capacity()), (100, usize::MAX));
+        tokio::select! {
+            biased;
+            sent = p.cast("+1") => sent.unwrap(),
+            _ = async {} => panic!("send waited"),
+        }
+        for (i, reply) in replies.into_iter().enumerate() {
+            assert_eq!(reply.await.unwrap().unwrap(), i as i32 + 1);
+        }
+        assert_eq!(p.send("get").await.unwrap(), 101);
+        assert!(p.is_empty());
+    }
+
+    #[tokio::test]
+    async fn broadcast_reaches_every_actor() {
+        let mut pids: Vec<Pid<&str, i32>> =
+            (0..3).map(|_| Actor::spawn(10, 0i32).unwrap()).collect();
+        let replies: Vec<_> = broadcast(&pids, "+1")
+            .await
+            .into_iter()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(replies, [1, 1, 1]);
+
+        // the panic kills the actor, the others still reply
+        let dead = Actor::spawn(10, Fragile(0)).unwrap();
+        assert!(dead.send("boom").await.is_err());
+        pids.insert(1, dead);
+        let replies = broadcast(&pids, "+1").await;
+        assert_eq!(replies[0].as_ref().unwrap(), &2);
+        let err = replies[1].as_ref().unwrap_err();
+        assert_eq!(err.downcast_ref(), Some(&ActorError::Closed));
+        assert_eq!(replies[3].as_ref().unwrap(), &2);
+    }
+
+    #[tokio::test]
+    async fn stopped_actors_finish_their_mailbox() {
+        let p: Pid<&str, i32> = Actor::spawn(10, 0i32).unwrap();
+        let other = p.clone();
+        assert_eq!(p.send("+1").await.unwrap(), 1);
+        let queued = p.try_send("+1").unwrap();
+        p.stop_and_wait().await;
+
+        assert_eq!(queued.await.unwrap().unwrap(), 2);
+        let err = other.send("+1").await.unwrap_err();
+        assert_eq!(err.downcast_ref(), Some(&ActorError::Closed));
+    }
+
+    #[tokio::test]
+    async fn state_is_inspected() {
+        let p: Pid<&str, i32> = Actor::spawn(10, 0i32).unwrap();
+        p.send("+1").await.unwrap();
+        p.send("+1").await.unwrap();
+        assert_eq!(p.inspect(|n: &i32| *n).await.unwrap(), 2);
+        // the state's type is checked
+        let err = p.inspect(|n: &u8| *n).await.unwrap_err();
+        assert_eq!(err.downcast_ref(), Some(&ActorError::WrongState("u8")));
+
+        let err = p.inspect(|_: &i32| panic!("boom")).await.unwrap_err();
+        assert_eq!(err.downcast_ref(), Some(&ActorError::Panicked));
+        assert_eq!(p.send("+1").await.unwrap(), 3);
+    }
+
+    // records every request it handles
+    struct Recorder(std::sync::Arc<std::sync::Mutex<Vec<&'static str>>>);
+
+    impl HandleCall for Recorder {
+        type Request = &'static str;
+        type Reply = ();
+
+        fn handle_call(&mut self, request: &Self::Request) -> Result<Self::Reply> {
+            self.0.lock().unwrap().push(request);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn cancelled_requests_are_skipped() {
+        let handled = std::sync::Arc::default();
+        let p: Pid<&str, ()> = Actor::spawn(10, Recorder(std::sync::Arc::clone(&handled))).unwrap();
+
+        // queue the request, then drop the future before the actor gets to it
+        tokio::select! {
+            biased;
+            _ = p.send("cancelled") => unreachable!(),
+            _ = async {} => {}
+        }
+        p.send("done").await.unwrap();
+        assert_eq!(*handled.lock().unwrap(), ["done"]);
+    }
+
+    #[cfg(feature = "metrics")]
+    #[tokio::test]
+    async fn latency_is_bucketed() {
+        struct Sleeper;
+
+        impl HandleCall for Sleeper {
+            type Request = u64;
+            type Reply = ();
+
+            fn handle_call(&mut self, millis: &u64) -> Result<()> {
+                std::thread::sleep(Duration::from_millis(*millis));
+                Ok(())
+            }
+        }
+
+        let p: Pid<u64, ()> = Actor::spawn(10, Sleeper).unwrap();
+        for millis in [0, 0, 0, 20, 30] {
+            p.send(millis).await.unwrap();
+        }
+        assert_eq!(p.metrics().processed(), 5);
+        assert_eq!(p.metrics().latency_histogram(), [3, 0, 2, 0, 0]);
+    }
+
+    mod span {
+        use std::sync::{Arc, Mutex};
+        use tracing::{
+            field::{Field, Visit},
+            span::{Id, Record},
+            Subscriber,
+        };
+        use tracing_subscriber::{layer::Context, prelude::*, registry::LookupSpan, Layer};
+
+        // collects (span name, status) pairs
+        #[derive(Clone, Default)]
+        pub struct StatusLayer(pub Arc<Mutex<Vec<(String, String)>>>);
+
+        struct StatusVisitor(Option<String>);
+
+        impl Visit for StatusVisitor {
+            fn record_str(&mut self, field: &Field, value: &str) {
+                if field.name() == "status" {
+                    self.0 = Some(value.to_string());
+                }
+            }
+
+            fn record_debug(&mut self, _field: &Field, _value: &dyn std::fmt::Debug) {}
+        }
+
+        impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for StatusLayer {
+            fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+                let mut visitor = StatusVisitor(None);
+                values.record(&mut visitor);
+                if let (Some(status), Some(span)) = (visitor.0, ctx.span(id)) {
+                    self.0
+                        .lock()
+                        .unwrap()
+                        .push((span.name().to_string(), status));
+                }
+            }
+        }
+
+        pub fn subscriber(layer: StatusLayer) -> impl Subscriber + Send + Sync {
+            tracing_subscriber::registry().with(layer)
+        }
+    }
+
+    #[tokio::test]
+    async fn handle_call_is_traced() {
+        let layer = span::StatusLayer::default();
+        let _guard = tracing::subscriber::set_default(span::subscriber(layer.clone()));
+
+        let p: Pid<&str, i32> = Actor::spawn(10, 0i32).unwrap();
+        p.send("+1").await.unwrap();
+        p.send("-1").await.unwrap();
+        let spans = layer.0.lock().unwrap().clone();
+        let expected = ("handle_call".to_string(), "ok".to_string());
+        assert_eq!(spans, vec![expected.clone(), expected]);
+    }
 }
@@ -0,0 +1,198 @@
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    sync::{Arc, Mutex, Weak},
+    time::{Duration, Instant},
+};
+
+use tokio::task::JoinHandle;
+
+use crate::clock::{Clock, SystemClock};
+
+// `None` deadlines never expire
+type Entries<K, V> = Mutex<HashMap<K, (V, Option<Instant>)>>;
+
+/// An in-process map whose entries expire after a per-entry TTL.
+///
+/// Expired entries are dropped lazily on access, `spawn_sweeper` can be used
+/// to also purge entries that are never read again.
+pub struct Cache<K, V> {
+    entries: Arc<Entries<K, V>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl<K, V> Default for Cache<K, V>
+where
+    K: Hash + Eq,
+    V: Clone,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, V> Cache<K, V>
+where
+    K: Hash + Eq,
+    V: Clone,
+{
+    pub fn new() -> Self {
+        Self::with_clock(Arc::new(SystemClock))
+    }
+
+    pub fn with_clock(clock: Arc<dyn Clock>) -> Self {
+        Self {
+            entries: Default::default(),
+            clock,
+        }
+    }
+
+    /// Inserts or overwrites `k`, an overwrite restarts the TTL. A TTL past
+    /// what the clock can represent never expires.
+    pub fn insert_with_ttl(&self, k: K, v: V, ttl: Duration) {
+        let deadline = self.clock.now().checked_add(ttl);
+        self.entries.lock().unwrap().insert(k, (v, deadline));
+    }
+
+    pub fn get(&self, k: &K) -> Option<V> {
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get(k) {
+            Some((v, deadline)) if is_live(*deadline, self.clock.now()) => Some(v.clone()),
+            Some(_) => {
+                entries.remove(k);
+                None
+            }
+            None => None,
+        }
+    }
+
+    pub fn remove(&self, k: &K) -> Option<V> {
+        let (v, deadline) = self.entries.lock().unwrap().remove(k)?;
+        is_live(deadline, self.clock.now()).then_some(v)
+    }
+
+    /// Number of entries, including expired ones not purged yet.
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Removes every expired entry, returns how many were removed.
+    pub fn purge_expired(&self) -> usize {
+        purge(&self.entries, self.clock.as_ref())
+    }
+}
+
+impl<K, V> Cache<K, V>
+where
+    K: Hash + Eq + Send + 'static,
+    V: Send + 'static,
+{
+    /// Purges expired entries every `period` in the background, the task
+    /// stops once the cache is dropped. `period` must not be zero.
+    pub fn spawn_sweeper(&self, period: Duration) -> JoinHandle<()> {
+        assert!(!period.is_zero(), "the sweep period must not be zero");
+        let entries: Weak<Entries<K, V>> = Arc::downgrade(&self.entries);
+        let clock = self.clock.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            loop {
+                interval.tick().await;
+                match entries.upgrade() {
+                    Some(entries) => {
+                        purge(&entries, clock.as_ref());
+                    }
+                    None => break,
+                }
+            }
+        })
+    }
+}
+
+fn is_live(deadline: Option<Instant>, now: Instant) -> bool {
+    deadline.is_none_or(|deadline| deadline > now)
+}
+
+fn purge<K, V>(entries: &Entries<K, V>, clock: &dyn Clock) -> usize
+where
+    K: Hash + Eq,
+{
+    let now = clock.now();
+    let mut entries = entries.lock().unwrap();
+    let before = entries.len();
+    entries.retain(|_, (_, deadline)| is_live(*deadline, now));
+    before - entries.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+
+    fn cache() -> (Cache<&'static str, i32>, Arc<MockClock>) {
+        let clock = Arc::new(MockClock::new());
+        (Cache::with_clock(clock.clone()), clock)
+    }
+
+    #[test]
+    fn it_works() {
+        let (cache, _) = cache();
+        cache.insert_with_ttl("a", 1, Duration::from_secs(1));
+        assert_eq!(cache.get(&"a"), Some(1));
+        assert_eq!(cache.get(&"b"), None);
+    }
+
+    #[test]
+    fn expired_entries_are_evicted() {
+        let (cache, clock) = cache();
+        cache.insert_with_ttl("a", 1, Duration::from_secs(1));
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(cache.get(&"a"), None);
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn overwrite_refreshes_ttl() {
+        let (cache, clock) = cache();
+        cache.insert_with_ttl("a", 1, Duration::from_secs(10));
+        clock.advance(Duration::from_secs(8));
+        cache.insert_with_ttl("a", 2, Duration::from_secs(10));
+        clock.advance(Duration::from_secs(8));
+        assert_eq!(cache.get(&"a"), Some(2));
+        clock.advance(Duration::from_secs(2));
+        assert_eq!(cache.get(&"a"), None);
+    }
+
+    #[test]
+    fn ttls_past_the_clock_never_expire() {
+        let (cache, clock) = cache();
+        cache.insert_with_ttl("a", 1, Duration::MAX);
+        clock.advance(Duration::from_secs(3600));
+        assert_eq!(cache.purge_expired(), 0);
+        assert_eq!(cache.get(&"a"), Some(1));
+    }
+
+    #[tokio::test]
+    #[should_panic(expected = "the sweep period must not be zero")]
+    async fn sweeper_rejects_a_zero_period() {
+        let (cache, _) = cache();
+        cache.spawn_sweeper(Duration::ZERO);
+    }
+
+    #[tokio::test]
+    async fn sweeper_purges_unread_entries() {
+        let (cache, clock) = cache();
+        cache.insert_with_ttl("a", 1, Duration::from_secs(1));
+        cache.insert_with_ttl("b", 2, Duration::from_secs(60));
+        let sweeper = cache.spawn_sweeper(Duration::from_millis(5));
+        clock.advance(Duration::from_secs(2));
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert_eq!(cache.len(), 1);
+
+        drop(cache);
+        sweeper.await.unwrap();
+    }
+}
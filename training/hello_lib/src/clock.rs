@@ -0,0 +1,81 @@
+use std::{
+    fmt::Debug,
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime},
+};
+
+/// Source of the current time, injectable so expiry logic can be tested
+/// without sleeping.
+pub trait Clock: Debug + Send + Sync {
+    fn now(&self) -> Instant;
+
+    /// The wall-clock time, for deadlines that have to outlive the process.
+    fn system_time(&self) -> SystemTime;
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn system_time(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// A clock that only moves when `advance` is called.
+#[derive(Debug)]
+pub struct MockClock {
+    // the wall-clock time moves along with the instant
+    now: Mutex<(Instant, SystemTime)>,
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MockClock {
+    pub fn new() -> Self {
+        Self {
+            now: Mutex::new((Instant::now(), SystemTime::now())),
+        }
+    }
+
+    pub fn advance(&self, dur: Duration) {
+        let mut now = self.now.lock().unwrap();
+        now.0 += dur;
+        now.1 += dur;
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        self.now.lock().unwrap().0
+    }
+
+    fn system_time(&self) -> SystemTime {
+        self.now.lock().unwrap().1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_works() {
+        let clock = MockClock::new();
+        let start = clock.now();
+        let wall = clock.system_time();
+        assert_eq!(clock.now(), start);
+        clock.advance(Duration::from_secs(10));
+        assert_eq!(clock.now() - start, Duration::from_secs(10));
+        let elapsed = clock.system_time().duration_since(wall).unwrap();
+        assert_eq!(elapsed, Duration::from_secs(10));
+    }
+}
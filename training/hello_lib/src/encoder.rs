@@ -1,9 +1,65 @@
-use anyhow::Result;
+use anyhow::{bail, Result};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
+/// Binary encoding: integers are fixed-size little-endian, strings and
+/// vecs are prefixed with their length as a `u32`, a `bool` is one byte, an
+/// `Option` a tag byte followed by the value if there is one. Each component
+/// of an `Event` is prefixed with its encoded length as a `u32`.
 pub trait Encoder {
-    fn encode(&self) -> Result<Vec<u8>>;
+    /// Appends the encoded value to `buf`, nested values share the buffer.
+    fn encode_into(&self, buf: &mut Vec<u8>) -> Result<()>;
+
+    fn encode(&self) -> Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        self.encode_into(&mut buf)?;
+        Ok(buf)
+    }
+}
+
+pub trait Decoder {
+    fn decode(buf: &mut &[u8]) -> Result<Self>
+    where
+        Self: Sized;
+}
+
+/// A format values can be written in and read back from.
+pub trait Serializer<T> {
+    fn serialize(&self, value: &T) -> Result<Vec<u8>>;
+    fn deserialize(&self, buf: &[u8]) -> Result<T>;
+}
+
+/// The native layout produced by `Encoder`.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct BinarySerializer;
+
+impl<T: Encoder + Decoder> Serializer<T> for BinarySerializer {
+    fn serialize(&self, value: &T) -> Result<Vec<u8>> {
+        value.encode()
+    }
+
+    fn deserialize(&self, mut buf: &[u8]) -> Result<T> {
+        let value = T::decode(&mut buf)?;
+        if !buf.is_empty() {
+            bail!("{} trailing bytes after value", buf.len());
+        }
+        Ok(value)
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct JsonSerializer;
+
+impl<T: Serialize + DeserializeOwned> Serializer<T> for JsonSerializer {
+    fn serialize(&self, value: &T) -> Result<Vec<u8>> {
+        Ok(serde_json::to_vec(value)?)
+    }
+
+    fn deserialize(&self, buf: &[u8]) -> Result<T> {
+        Ok(serde_json::from_slice(buf)?)
+    }
 }
 
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct Event<Id, Data> {
     id: Id,
     data: Data,
@@ -13,6 +69,14 @@ impl<Id, Data> Event<Id, Data> {
     pub fn new(id: Id, data: Data) -> Self {
         Self { id, data }
     }
+
+    pub fn encode_with<S: Serializer<Self>>(&self, s: &S) -> Result<Vec<u8>> {
+        s.serialize(self)
+    }
+
+    pub fn decode_with<S: Serializer<Self>>(s: &S, buf: &[u8]) -> Result<Self> {
+        s.deserialize(buf)
+    }
 }
 
 impl<Id, Data> Encoder for Event<Id, Data>
@@ -20,9 +84,201 @@ where
     Id: Encoder,
     Data: Encoder,
 {
-    fn encode(&self) -> Result<Vec<u8>> {
-        let mut result = self.id.encode()?;
-        result.append(&mut self.data.encode()?);
+    fn encode_into(&self, buf: &mut Vec<u8>) -> Result<()> {
+        encode_prefixed(&self.id, buf)?;
+        encode_prefixed(&self.data, buf)
+    }
+}
+
+impl<Id, Data> Decoder for Event<Id, Data>
+where
+    Id: Decoder,
+    Data: Decoder,
+{
+    fn decode(buf: &mut &[u8]) -> Result<Self> {
+        let id = decode_prefixed(buf, "event id")?;
+        let data = decode_prefixed(buf, "event data")?;
+        Ok(Self { id, data })
+    }
+}
+
+/// Prefixes `bytes` with its length as a big-endian `u32`, so framed values
+/// can be concatenated and split back apart with `deframe`.
+pub fn frame(bytes: &[u8]) -> Result<Vec<u8>> {
+    let len = match u32::try_from(bytes.len()) {
+        Ok(len) => len,
+        Err(_) => bail!("frame of {} bytes is 4GiB or larger", bytes.len()),
+    };
+    let mut buf = Vec::with_capacity(4 + bytes.len());
+    buf.extend_from_slice(&len.to_be_bytes());
+    buf.extend_from_slice(bytes);
+    Ok(buf)
+}
+
+/// Splits the first frame off `buf` and returns its payload.
+pub fn deframe(buf: &mut &[u8]) -> Result<Vec<u8>> {
+    let len = u32::from_be_bytes(take(buf, "frame length")?) as usize;
+    if len > buf.len() {
+        bail!("truncated frame: need {} bytes, got {}", len, buf.len());
+    }
+    let (data, rest) = buf.split_at(len);
+    *buf = rest;
+    Ok(data.to_vec())
+}
+
+// Lengths are encoded as a little-endian u32 in front of the payload.
+fn encode_len(len: usize, buf: &mut Vec<u8>) -> Result<()> {
+    let len = u32::try_from(len)?;
+    buf.extend_from_slice(&len.to_le_bytes());
+    Ok(())
+}
+
+fn decode_len(buf: &mut &[u8]) -> Result<usize> {
+    Ok(u32::from_le_bytes(take(buf, "length prefix")?) as usize)
+}
+
+// Encodes `value` behind its length, the length is patched in once known.
+fn encode_prefixed<T: Encoder>(value: &T, buf: &mut Vec<u8>) -> Result<()> {
+    let start = buf.len();
+    buf.extend_from_slice(&[0; 4]);
+    value.encode_into(buf)?;
+    let len = u32::try_from(buf.len() - start - 4)?;
+    buf[start..start + 4].copy_from_slice(&len.to_le_bytes());
+    Ok(())
+}
+
+// Decodes a value written by `encode_prefixed`, which must fill its length exactly.
+fn decode_prefixed<T: Decoder>(buf: &mut &[u8], what: &str) -> Result<T> {
+    let len = decode_len(buf)?;
+    if len > buf.len() {
+        bail!(
+            "{} length {} exceeds remaining {} bytes",
+            what,
+            len,
+            buf.len()
+        );
+    }
+    let (mut data, rest) = buf.split_at(len);
+    *buf = rest;
+    let value = T::decode(&mut data)?;
+    if !data.is_empty() {
+        bail!("{} trailing bytes after {}", data.len(), what);
+    }
+    Ok(value)
+}
+
+// splits the first N bytes off `buf`
+fn take<const N: usize>(buf: &mut &[u8], what: &str) -> Result<[u8; N]> {
+    if buf.len() < N {
+        bail!("truncated {}: need {} bytes, got {}", what, N, buf.len());
+    }
+    let (data, rest) = buf.split_at(N);
+    *buf = rest;
+    Ok(data.try_into()?)
+}
+
+macro_rules! impl_int {
+    ($($t:ty),*) => {$(
+        impl Encoder for $t {
+            fn encode_into(&self, buf: &mut Vec<u8>) -> Result<()> {
+                buf.extend_from_slice(&self.to_le_bytes());
+                Ok(())
+            }
+        }
+
+        impl Decoder for $t {
+            fn decode(buf: &mut &[u8]) -> Result<Self> {
+                Ok(<$t>::from_le_bytes(take(buf, stringify!($t))?))
+            }
+        }
+    )*};
+}
+
+impl_int!(u8, i32, u32, u64);
+
+impl Encoder for bool {
+    fn encode_into(&self, buf: &mut Vec<u8>) -> Result<()> {
+        (*self as u8).encode_into(buf)
+    }
+}
+
+impl Decoder for bool {
+    fn decode(buf: &mut &[u8]) -> Result<Self> {
+        match u8::decode(buf)? {
+            0 => Ok(false),
+            1 => Ok(true),
+            b => bail!("invalid bool byte {}", b),
+        }
+    }
+}
+
+impl<T: Encoder> Encoder for Option<T> {
+    fn encode_into(&self, buf: &mut Vec<u8>) -> Result<()> {
+        match self {
+            None => 0u8.encode_into(buf),
+            Some(value) => {
+                1u8.encode_into(buf)?;
+                value.encode_into(buf)
+            }
+        }
+    }
+}
+
+impl<T: Decoder> Decoder for Option<T> {
+    fn decode(buf: &mut &[u8]) -> Result<Self> {
+        match u8::decode(buf)? {
+            0 => Ok(None),
+            1 => Ok(Some(T::decode(buf)?)),
+            tag => bail!("invalid option tag {}", tag),
+        }
+    }
+}
+
+impl Encoder for String {
+    fn encode_into(&self, buf: &mut Vec<u8>) -> Result<()> {
+        encode_len(self.len(), buf)?;
+        buf.extend_from_slice(self.as_bytes());
+        Ok(())
+    }
+}
+
+impl Decoder for String {
+    fn decode(buf: &mut &[u8]) -> Result<Self> {
+        let len = decode_len(buf)?;
+        if len > buf.len() {
+            bail!(
+                "string length {} exceeds remaining {} bytes",
+                len,
+                buf.len()
+            );
+        }
+        let (data, rest) = buf.split_at(len);
+        *buf = rest;
+        Ok(String::from_utf8(data.to_vec())?)
+    }
+}
+
+impl<T: Encoder> Encoder for Vec<T> {
+    fn encode_into(&self, buf: &mut Vec<u8>) -> Result<()> {
+        encode_len(self.len(), buf)?;
+        for item in self {
+            item.encode_into(buf)?;
+        }
+        Ok(())
+    }
+}
+
+impl<T: Decoder> Decoder for Vec<T> {
+    fn decode(buf: &mut &[u8]) -> Result<Self> {
+        let len = decode_len(buf)?;
+        // every encoded item takes at least one byte, so a larger count can't be valid
+        if len > buf.len() {
+            bail!("vec length {} exceeds remaining {} bytes", len, buf.len());
+        }
+        let mut result = Vec::with_capacity(len);
+        for _ in 0..len {
+            result.push(T::decode(buf)?);
+        }
         Ok(result)
     }
 }
@@ -30,23 +286,196 @@ where
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::fmt::Debug;
 
-    impl Encoder for i32 {
-        fn encode(&self) -> Result<Vec<u8>> {
-            //todo: encode
-            Ok(vec![1, 2, 3, 4])
+    #[test]
+    fn it_works() {
+        let e = Event::new(1, "Hello World!".to_string());
+        let _ = e.encode().unwrap();
+    }
+
+    #[test]
+    fn event_roundtrip() {
+        let e = Event::new(-7, "Hello World!".to_string());
+        let buf = e.encode().unwrap();
+        assert_eq!(&buf[..8], [4, 0, 0, 0, 0xf9, 0xff, 0xff, 0xff]);
+        assert_eq!(&buf[8..16], [16, 0, 0, 0, 12, 0, 0, 0]);
+        let mut data = buf.as_slice();
+        assert_eq!(Event::<i32, String>::decode(&mut data).unwrap(), e);
+        assert!(data.is_empty());
+
+        let mut data: &[u8] = &[1, 2, 3];
+        assert!(i32::decode(&mut data).is_err());
+    }
+
+    #[test]
+    fn nested_events_encode_into_one_buffer() {
+        let inner = Event::new("login".to_string(), vec!["lxb".to_string()]);
+        let e = Event::new(1, inner);
+        let buf = e.encode().unwrap();
+
+        // each component behind its length, the inner event is one component
+        let inner = e.data.encode().unwrap();
+        let mut expected = vec![4, 0, 0, 0, 1, 0, 0, 0];
+        expected.extend((inner.len() as u32).to_le_bytes());
+        expected.extend(inner);
+        assert_eq!(buf, expected);
+
+        let mut data = buf.as_slice();
+        assert_eq!(Event::decode(&mut data).unwrap(), e);
+    }
+
+    // encodes and decodes `value`, panicking with both encodings if the
+    // decoded value differs, returns the encoding
+    fn assert_roundtrip<T: Encoder + Decoder + PartialEq + Debug>(value: T) -> Vec<u8> {
+        let buf = value
+            .encode()
+            .unwrap_or_else(|e| panic!("failed to encode {:?}: {:#}", value, e));
+        let mut data = buf.as_slice();
+        let decoded = T::decode(&mut data)
+            .unwrap_or_else(|e| panic!("failed to decode {:?} from {:02x?}: {:#}", value, buf, e));
+        assert!(
+            data.is_empty(),
+            "{} trailing bytes after decoding {:?} from {:02x?}",
+            data.len(),
+            value,
+            buf
+        );
+        if decoded != value {
+            let reencoded = decoded.encode().unwrap_or_default();
+            panic!(
+                "roundtrip mismatch\n  value:   {:?}\n  decoded: {:?}\n  bytes:   {:02x?}\n  decoded bytes: {:02x?}",
+                value, decoded, buf, reencoded
+            );
         }
+        buf
     }
 
-    impl Encoder for String {
-        fn encode(&self) -> Result<Vec<u8>> {
-            Ok(self.as_bytes().to_vec())
+    #[test]
+    fn primitives_roundtrip() {
+        assert_eq!(assert_roundtrip(7u8), [7]);
+        assert_eq!(assert_roundtrip(-2i32), [0xfe, 0xff, 0xff, 0xff]);
+        assert_eq!(assert_roundtrip(0x0102_0304u32), [4, 3, 2, 1]);
+        assert_eq!(assert_roundtrip(u64::MAX), [0xff; 8]);
+        assert_eq!(assert_roundtrip(true), [1]);
+        assert_eq!(assert_roundtrip(Some(false)), [1, 0]);
+        assert_eq!(assert_roundtrip(None::<u32>), [0]);
+        assert_eq!(assert_roundtrip("ab".to_string()), [2, 0, 0, 0, b'a', b'b']);
+
+        let mut data: &[u8] = &[2];
+        assert!(bool::decode(&mut data).is_err());
+        let mut data: &[u8] = &[1, 0, 0];
+        assert!(u32::decode(&mut data).is_err());
+    }
+
+    #[test]
+    fn nested_containers_roundtrip() {
+        let v = vec![Some("a".to_string()), None, Some(String::new())];
+        let buf = assert_roundtrip(v);
+        assert_eq!(buf.len(), 4 + (1 + 4 + 1) + 1 + (1 + 4));
+
+        let inner = Event::new("login".to_string(), vec![Some(1u64), None]);
+        assert_roundtrip(Event::new(-1, inner));
+    }
+
+    // decodes any byte as false, so true doesn't survive a roundtrip
+    #[derive(Debug, PartialEq)]
+    struct Lossy(bool);
+
+    impl Encoder for Lossy {
+        fn encode_into(&self, buf: &mut Vec<u8>) -> Result<()> {
+            self.0.encode_into(buf)
+        }
+    }
+
+    impl Decoder for Lossy {
+        fn decode(buf: &mut &[u8]) -> Result<Self> {
+            u8::decode(buf)?;
+            Ok(Self(false))
         }
     }
 
     #[test]
-    fn it_works() {
-        let e = Event::new(1, "Hello World!".to_string());
-        let _ = e.encode().unwrap();
+    #[should_panic(expected = "bytes:   [01]\n  decoded bytes: [00]")]
+    fn roundtrip_mismatches_show_the_bytes() {
+        assert_roundtrip(Lossy(true));
+    }
+
+    #[test]
+    fn string_roundtrip() {
+        let s = "Hello World!".to_string();
+        let buf = s.encode().unwrap();
+        let mut data = buf.as_slice();
+        assert_eq!(String::decode(&mut data).unwrap(), s);
+        assert!(data.is_empty());
+
+        let v = vec!["a".to_string(), "bc".to_string()];
+        let buf = v.encode().unwrap();
+        let mut data = buf.as_slice();
+        assert_eq!(Vec::<String>::decode(&mut data).unwrap(), v);
+        assert!(data.is_empty());
+    }
+
+    #[test]
+    fn encode_with_serializers() {
+        let e = Event::new("login".to_string(), vec!["lxb".to_string()]);
+
+        let buf = e.encode_with(&BinarySerializer).unwrap();
+        assert_eq!(buf, e.encode().unwrap());
+        assert_eq!(Event::decode_with(&BinarySerializer, &buf).unwrap(), e);
+
+        let buf = e.encode_with(&JsonSerializer).unwrap();
+        assert_eq!(buf, br#"{"id":"login","data":["lxb"]}"#);
+        assert_eq!(Event::decode_with(&JsonSerializer, &buf).unwrap(), e);
+    }
+
+    #[test]
+    fn framed_events_split_back_in_order() {
+        let first = Event::new(1, "login".to_string());
+        let second = Event::new(2, "logout".to_string());
+        let mut buf = frame(&first.encode().unwrap()).unwrap();
+        buf.extend(frame(&second.encode().unwrap()).unwrap());
+        assert_eq!(&buf[..4], [0, 0, 0, 21]);
+
+        let mut data = buf.as_slice();
+        let payload = deframe(&mut data).unwrap();
+        assert_eq!(BinarySerializer.deserialize(&payload).ok(), Some(first));
+        let payload = deframe(&mut data).unwrap();
+        assert_eq!(BinarySerializer.deserialize(&payload).ok(), Some(second));
+        assert!(data.is_empty());
+
+        let mut data = &buf[..buf.len() - 1];
+        deframe(&mut data).unwrap();
+        let err = deframe(&mut data).unwrap_err();
+        assert_eq!(err.to_string(), "truncated frame: need 22 bytes, got 21");
+        let mut data: &[u8] = &[0, 0];
+        assert!(deframe(&mut data).is_err());
+    }
+
+    #[test]
+    fn oversized_length_prefix_is_rejected() {
+        let mut buf = 100u32.to_le_bytes().to_vec();
+        buf.extend_from_slice(b"short");
+        let mut data = buf.as_slice();
+        let err = String::decode(&mut data).unwrap_err();
+        assert!(err.to_string().contains("exceeds remaining"));
+
+        let mut data = buf.as_slice();
+        let err = Vec::<String>::decode(&mut data).unwrap_err();
+        assert!(err.to_string().contains("exceeds remaining"));
+
+        let mut data: &[u8] = &[1, 0];
+        assert!(String::decode(&mut data).is_err());
+
+        let mut data: &[u8] = &[9, 0, 0, 0, 1, 0, 0, 0];
+        let err = Event::<i32, i32>::decode(&mut data).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "event id length 9 exceeds remaining 4 bytes"
+        );
+        // an id prefix longer than the id leaves bytes behind
+        let mut data: &[u8] = &[5, 0, 0, 0, 1, 0, 0, 0, 0, 4, 0, 0, 0, 2, 0, 0, 0];
+        let err = Event::<i32, i32>::decode(&mut data).unwrap_err();
+        assert_eq!(err.to_string(), "1 trailing bytes after event id");
     }
 }
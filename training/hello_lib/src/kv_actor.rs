@@ -0,0 +1,66 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+
+use crate::actor::HandleCall;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KvCommand {
+    Get(String),
+    Put(String, Vec<u8>),
+    Del(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KvReply {
+    /// value found by `Get`, or removed by `Del`
+    Value(Vec<u8>),
+    NotFound,
+    /// `Put` stored the value
+    Done,
+}
+
+/// An in-memory KV store usable as actor state:
+/// `Actor::spawn(10, HashMap::new())`.
+impl HandleCall for HashMap<String, Vec<u8>> {
+    type Request = KvCommand;
+    type Reply = KvReply;
+
+    fn handle_call(&mut self, request: &Self::Request) -> Result<Self::Reply> {
+        let value = match request {
+            KvCommand::Get(key) => self.get(key).cloned(),
+            KvCommand::Put(key, value) => {
+                self.insert(key.clone(), value.clone());
+                return Ok(KvReply::Done);
+            }
+            KvCommand::Del(key) => self.remove(key),
+        };
+        Ok(value.map_or(KvReply::NotFound, KvReply::Value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::actor::{Actor, Pid};
+
+    #[tokio::test]
+    async fn it_works() {
+        let p: Pid<KvCommand, KvReply> = Actor::spawn(10, HashMap::new()).unwrap();
+        let key = || "hello".to_string();
+
+        let r = p.send(KvCommand::Get(key())).await.unwrap();
+        assert_eq!(r, KvReply::NotFound);
+        let r = p
+            .send(KvCommand::Put(key(), b"world".to_vec()))
+            .await
+            .unwrap();
+        assert_eq!(r, KvReply::Done);
+        let r = p.send(KvCommand::Get(key())).await.unwrap();
+        assert_eq!(r, KvReply::Value(b"world".to_vec()));
+        let r = p.send(KvCommand::Del(key())).await.unwrap();
+        assert_eq!(r, KvReply::Value(b"world".to_vec()));
+        let r = p.send(KvCommand::Get(key())).await.unwrap();
+        assert_eq!(r, KvReply::NotFound);
+    }
+}
@@ -1,8 +1,14 @@
 pub mod actor;
+pub mod cache;
+pub mod clock;
 pub mod encoder;
 pub mod fibonacci;
+pub mod kv_actor;
+pub mod pool;
+pub mod registry;
 pub mod strtok;
 pub mod ticket;
+pub mod ticket_office;
 pub mod user;
 
 #[cfg(test)]
@@ -0,0 +1,90 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use anyhow::{bail, Result};
+
+use crate::actor::{Actor, HandleCall, Pid};
+
+/// A fixed set of identical actors, each `send` goes to the next one in
+/// turn.
+pub struct Pool<Request, Reply> {
+    pids: Vec<Pid<Request, Reply>>,
+    next: AtomicUsize,
+}
+
+impl<Request, Reply> Pool<Request, Reply>
+where
+    Request: Send + 'static,
+    Reply: Send + 'static,
+{
+    /// Spawns `size` actors, each with a clone of `state`.
+    pub fn spawn<State>(size: usize, max_msg_len: usize, state: State) -> Result<Self>
+    where
+        State: HandleCall<Request = Request, Reply = Reply> + Clone + Send + 'static,
+    {
+        Self::spawn_with(size, max_msg_len, |_| state.clone())
+    }
+
+    /// Spawns `size` actors, the i-th with the state `factory(i)`.
+    pub fn spawn_with<State, F>(size: usize, max_msg_len: usize, factory: F) -> Result<Self>
+    where
+        State: HandleCall<Request = Request, Reply = Reply> + Send + 'static,
+        F: FnMut(usize) -> State,
+    {
+        if size == 0 {
+            bail!("a pool needs at least one actor");
+        }
+        let pids = (0..size)
+            .map(factory)
+            .map(|state| Actor::spawn(max_msg_len, state))
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            pids,
+            next: AtomicUsize::new(0),
+        })
+    }
+}
+
+impl<Request, Reply> Pool<Request, Reply> {
+    /// Sends `data` to the next actor in turn and returns its reply.
+    pub async fn send(&self, data: Request) -> Result<Reply> {
+        let i = self.next.fetch_add(1, Ordering::Relaxed) % self.pids.len();
+        self.pids[i].send(data).await
+    }
+
+    pub fn pids(&self) -> &[Pid<Request, Reply>] {
+        &self.pids
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // replies with its index in the pool
+    #[derive(Clone)]
+    struct Echo(usize);
+
+    impl HandleCall for Echo {
+        type Request = ();
+        type Reply = usize;
+
+        fn handle_call(&mut self, _: &()) -> Result<usize> {
+            Ok(self.0)
+        }
+    }
+
+    #[tokio::test]
+    async fn sends_are_spread_round_robin() {
+        let pool = Pool::spawn_with(4, 10, Echo).unwrap();
+        let mut counts = [0; 4];
+        for _ in 0..100 {
+            counts[pool.send(()).await.unwrap()] += 1;
+        }
+        assert_eq!(counts, [25; 4]);
+    }
+
+    #[test]
+    fn empty_pools_are_rejected() {
+        assert!(Pool::<(), usize>::spawn(0, 10, Echo(0)).is_err());
+    }
+}
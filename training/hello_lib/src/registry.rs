@@ -0,0 +1,78 @@
+use std::any::Any;
+
+use dashmap::DashMap;
+
+use crate::actor::Pid;
+
+/// Actor `Pid`s looked up by name, `Actor::spawn_named` registers into it.
+#[derive(Default)]
+pub struct Registry {
+    pids: DashMap<String, Box<dyn Any + Send + Sync>>,
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers `pid` under `name`, replacing the one registered before.
+    pub fn register<Request, Reply>(&self, name: impl Into<String>, pid: Pid<Request, Reply>)
+    where
+        Request: Send + 'static,
+        Reply: Send + 'static,
+    {
+        self.pids.insert(name.into(), Box::new(pid));
+    }
+
+    /// Returns the `Pid` registered under `name`, `None` if there is none or
+    /// its actor handles other request or reply types.
+    pub fn lookup<Request, Reply>(&self, name: &str) -> Option<Pid<Request, Reply>>
+    where
+        Request: 'static,
+        Reply: 'static,
+    {
+        self.pids.get(name)?.downcast_ref().cloned()
+    }
+
+    /// Removes the `Pid` registered under `name`, returns whether there was
+    /// one.
+    pub fn unregister(&self, name: &str) -> bool {
+        self.pids.remove(name).is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::actor::{Actor, HandleCall};
+    use anyhow::Result;
+
+    struct Counter(i32);
+
+    impl HandleCall for Counter {
+        type Request = i32;
+        type Reply = i32;
+
+        fn handle_call(&mut self, delta: &i32) -> Result<i32> {
+            self.0 += delta;
+            Ok(self.0)
+        }
+    }
+
+    #[tokio::test]
+    async fn pids_are_looked_up_by_name() {
+        let registry = Registry::new();
+        let pid = Actor::spawn_named(&registry, "counter", 10, Counter(0)).unwrap();
+        pid.send(1).await.unwrap();
+
+        let found = registry.lookup::<i32, i32>("counter").unwrap();
+        assert_eq!(found.send(2).await.unwrap(), 3);
+        // wrong types don't match
+        assert!(registry.lookup::<i64, i32>("counter").is_none());
+        assert!(registry.lookup::<i32, ()>("counter").is_none());
+        assert!(registry.lookup::<i32, i32>("missing").is_none());
+
+        assert!(registry.unregister("counter"));
+        assert!(registry.lookup::<i32, i32>("counter").is_none());
+    }
+}
@@ -13,6 +13,83 @@ pub fn strtok<'a>(s: &'a mut &str, pat: char) -> &'a str {
     }
 }
 
+// strtok_any(s = "a\tb c", &[' ', '\t'])
+// return "a", s = "b c"
+
+pub fn strtok_any<'a>(s: &'a mut &str, pats: &[char]) -> &'a str {
+    match s.char_indices().find(|(_, c)| pats.contains(c)) {
+        Some((idx, c)) => {
+            let prefix = &s[..idx];
+            *s = &s[idx + c.len_utf8()..];
+            prefix
+        }
+        None => {
+            let prefix = *s;
+            *s = "";
+            prefix
+        }
+    }
+}
+
+// strtok_str(s = "a::b", "::")
+// return "a", s = "b"; an empty pat takes the whole string
+
+pub fn strtok_str<'a>(s: &'a mut &str, pat: &str) -> &'a str {
+    match s.find(pat) {
+        Some(idx) if !pat.is_empty() => {
+            let prefix = &s[..idx];
+            *s = &s[idx + pat.len()..];
+            prefix
+        }
+        _ => {
+            let prefix = *s;
+            *s = "";
+            prefix
+        }
+    }
+}
+
+// split_once_char("hello world", ' ')
+// return Some(("hello", "world")), s is left untouched
+
+pub fn split_once_char(s: &str, pat: char) -> Option<(&str, &str)> {
+    let idx = s.find(pat)?;
+    Some((&s[..idx], &s[idx + pat.len_utf8()..]))
+}
+
+// tokenize("a,b,", ',')
+// yields "a", "b", ""
+
+pub fn tokenize(s: &str, pat: char) -> Tokens<'_> {
+    Tokens { rest: Some(s), pat }
+}
+
+/// Tokens of a string split on a char, see `tokenize`.
+#[derive(Debug, Clone)]
+pub struct Tokens<'a> {
+    // `None` once the last token was yielded
+    rest: Option<&'a str>,
+    pat: char,
+}
+
+impl<'a> Iterator for Tokens<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<&'a str> {
+        let rest = self.rest?;
+        match split_once_char(rest, self.pat) {
+            Some((token, after)) => {
+                self.rest = Some(after);
+                Some(token)
+            }
+            None => {
+                self.rest = None;
+                Some(rest)
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -23,4 +100,52 @@ mod tests {
         assert_eq!(strtok(&mut s, ' '), "hello");
         assert_eq!(s, "world");
     }
+
+    #[test]
+    fn strtok_any_works() {
+        let mut s = "a\tb c";
+        assert_eq!(strtok_any(&mut s, &[' ', '\t']), "a");
+        assert_eq!(strtok_any(&mut s, &[' ', '\t']), "b");
+        assert_eq!(strtok_any(&mut s, &[' ', '\t']), "c");
+        assert_eq!(s, "");
+
+        let mut s = "x→y、z";
+        assert_eq!(strtok_any(&mut s, &['、', '→']), "x");
+        assert_eq!(strtok_any(&mut s, &['、', '→']), "y");
+        assert_eq!(s, "z");
+    }
+
+    #[test]
+    fn strtok_str_works() {
+        let mut s = "key=value";
+        assert_eq!(strtok_str(&mut s, "="), "key");
+        assert_eq!(s, "value");
+
+        let mut s = "a::b::c";
+        assert_eq!(strtok_str(&mut s, "::"), "a");
+        assert_eq!(strtok_str(&mut s, "::"), "b");
+        assert_eq!(strtok_str(&mut s, "::"), "c");
+        assert_eq!(s, "");
+
+        let mut s = "abc";
+        assert_eq!(strtok_str(&mut s, ""), "abc");
+        assert_eq!(s, "");
+    }
+
+    #[test]
+    fn tokenize_works() {
+        assert_eq!(tokenize("a,b,c", ',').collect::<Vec<_>>(), ["a", "b", "c"]);
+        assert_eq!(tokenize("a,", ',').collect::<Vec<_>>(), ["a", ""]);
+        assert_eq!(tokenize("", ',').collect::<Vec<_>>(), [""]);
+    }
+
+    #[test]
+    fn split_once_char_works() {
+        let s = "hello world";
+        assert_eq!(split_once_char(s, ' '), Some(("hello", "world")));
+        assert_eq!(s, "hello world");
+        assert_eq!(split_once_char(s, ','), None);
+        assert_eq!(split_once_char(",world", ','), Some(("", "world")));
+        assert_eq!(split_once_char("a→b", '→'), Some(("a", "b")));
+    }
 }
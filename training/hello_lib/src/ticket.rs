@@ -1,42 +1,212 @@
-use tokio::sync::{Semaphore, SemaphorePermit};
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use tokio::{
+    sync::{OwnedSemaphorePermit, Semaphore},
+    task::JoinHandle,
+    time::{sleep, timeout},
+};
 
 pub struct Meseum {
-    remaining_tickets: Semaphore,
+    remaining_tickets: Arc<Semaphore>,
+    // the semaphore only knows what's left
+    capacity: AtomicUsize,
 }
 
 impl Meseum {
     pub fn new(total: usize) -> Self {
         Self {
-            remaining_tickets: Semaphore::new(total),
+            remaining_tickets: Arc::new(Semaphore::new(total)),
+            capacity: AtomicUsize::new(total),
         }
     }
 
+    #[must_use = "holding the ticket reserves capacity; drop it only when done"]
     pub fn get_ticket(&self) -> Option<Ticket> {
-        match self.remaining_tickets.try_acquire() {
-            Ok(permit) => Some(Ticket::new(permit)),
-            Err(_) => None,
+        self.get_ticket_weighted(1)
+            .map(|ticket| Ticket::new(ticket.permit))
+    }
+
+    /// Takes one ticket using up `weight` of the capacity, e.g. a bus counts
+    /// for as many as its seats, or none if that much isn't left. A weight
+    /// of 0 gets no ticket.
+    #[must_use = "holding the ticket reserves capacity; drop it only when done"]
+    pub fn get_ticket_weighted(&self, weight: u32) -> Option<WeightedTicket> {
+        if weight == 0 {
+            return None;
+        }
+        let permit = self.try_take(weight)?;
+        Some(WeightedTicket { permit, weight })
+    }
+
+    fn try_take(&self, n: u32) -> Option<OwnedSemaphorePermit> {
+        self.remaining_tickets
+            .clone()
+            .try_acquire_many_owned(n)
+            .ok()
+    }
+
+    /// Waits until a ticket is available, visitors are served in the order
+    /// they arrive.
+    pub async fn acquire_ticket(&self) -> Ticket {
+        let permit = self.remaining_tickets.clone().acquire_owned().await;
+        // the semaphore is never closed
+        Ticket::new(permit.unwrap())
+    }
+
+    /// Like `acquire_ticket`, but gives up with `None` after `dur`.
+    pub async fn acquire_ticket_timeout(&self, dur: Duration) -> Option<Ticket> {
+        // a ticket freed as time runs out is either returned, the acquire is
+        // polled before the timer, or handed back when the acquire is dropped
+        timeout(dur, self.acquire_ticket()).await.ok()
+    }
+
+    /// Holds a ticket for `hold`, it goes back on sale unless the
+    /// reservation is confirmed by then. Must be called within a tokio
+    /// runtime.
+    pub fn reserve(&self, hold: Duration) -> Option<Reservation> {
+        let permit = Arc::new(Mutex::new(Some(self.try_take(1)?)));
+        let held = permit.clone();
+        let expiry = tokio::spawn(async move {
+            sleep(hold).await;
+            held.lock().unwrap().take();
+        });
+        Some(Reservation { permit, expiry })
+    }
+
+    /// Takes `n` tickets at once for a group, or none if fewer are available
+    /// or `n` is 0.
+    pub fn get_group(&self, n: u32) -> Option<BulkTicket> {
+        if n == 0 {
+            return None;
+        }
+        self.try_take(n).map(|permit| BulkTicket {
+            _permit: Some(permit),
+            count: n,
+        })
+    }
+
+    /// Takes as many tickets as are available right now, at most `n`.
+    pub fn get_up_to(&self, n: u32) -> BulkTicket {
+        loop {
+            let available = self.tickets().min(n as usize) as u32;
+            if available == 0 {
+                return BulkTicket::default();
+            }
+            // another caller may have taken some in between, retry with the new count
+            if let Ok(permit) = self
+                .remaining_tickets
+                .clone()
+                .try_acquire_many_owned(available)
+            {
+                return BulkTicket {
+                    _permit: Some(permit),
+                    count: available,
+                };
+            }
         }
     }
 
     pub fn tickets(&self) -> usize {
         self.remaining_tickets.available_permits()
     }
+
+    /// Opens `n` more tickets, beyond the capacity the museum started with
+    /// if need be.
+    pub fn add_tickets(&self, n: usize) {
+        self.capacity.fetch_add(n, Ordering::Relaxed);
+        self.remaining_tickets.add_permits(n);
+    }
+
+    /// Total number of tickets, sold or not.
+    pub fn capacity(&self) -> usize {
+        self.capacity.load(Ordering::Relaxed)
+    }
 }
 
 #[derive(Debug)]
-pub struct Ticket<'a> {
-    permit: SemaphorePermit<'a>,
+#[must_use = "holding the ticket reserves capacity; drop it only when done"]
+pub struct Ticket {
+    _permit: OwnedSemaphorePermit,
 }
 
-impl<'a> Drop for Ticket<'a> {
+impl Drop for Ticket {
     fn drop(&mut self) {
         println!("ticket freed");
     }
 }
 
-impl<'a> Ticket<'a> {
-    pub fn new(permit: SemaphorePermit<'a>) -> Self {
-        Self { permit }
+impl Ticket {
+    pub fn new(permit: OwnedSemaphorePermit) -> Self {
+        Self { _permit: permit }
+    }
+
+    /// Keeps the ticket, making it explicit that the caller owns the reservation.
+    pub fn hold(self) -> Self {
+        self
+    }
+}
+
+/// A ticket worth `weight` of the capacity, all of it released on drop.
+#[derive(Debug)]
+#[must_use = "holding the ticket reserves capacity; drop it only when done"]
+pub struct WeightedTicket {
+    // holds `weight` permits
+    permit: OwnedSemaphorePermit,
+    weight: u32,
+}
+
+impl WeightedTicket {
+    pub fn weight(&self) -> u32 {
+        self.weight
+    }
+}
+
+/// A ticket held for a while, see `Meseum::reserve`. Dropping it releases
+/// the ticket right away.
+#[derive(Debug)]
+#[must_use = "the reservation is released when dropped, confirm it to keep the ticket"]
+pub struct Reservation {
+    // taken by whichever of the expiry timer and `confirm` comes first
+    permit: Arc<Mutex<Option<OwnedSemaphorePermit>>>,
+    expiry: JoinHandle<()>,
+}
+
+impl Reservation {
+    /// Turns the reservation into a ticket, `None` if it already expired.
+    pub fn confirm(self) -> Option<Ticket> {
+        self.expiry.abort();
+        let permit = self.permit.lock().unwrap().take();
+        permit.map(Ticket::new)
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.permit.lock().unwrap().is_none()
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        self.expiry.abort();
+        self.permit.lock().unwrap().take();
+    }
+}
+
+/// Several tickets held together, all released on drop.
+#[derive(Debug, Default)]
+pub struct BulkTicket {
+    _permit: Option<OwnedSemaphorePermit>,
+    count: u32,
+}
+
+impl BulkTicket {
+    pub fn count(&self) -> u32 {
+        self.count
     }
 }
 
@@ -58,4 +228,129 @@ mod tests {
         assert!(meseum.get_ticket().is_some());
         println!("------------------");
     }
+
+    #[test]
+    fn bound_ticket_keeps_reservation() {
+        let meseum = Meseum::new(2);
+        let _ = meseum.get_ticket();
+        assert_eq!(meseum.tickets(), 2);
+
+        let ticket = meseum.get_ticket().unwrap().hold();
+        assert_eq!(meseum.tickets(), 1);
+        drop(ticket);
+        assert_eq!(meseum.tickets(), 2);
+    }
+
+    #[tokio::test]
+    async fn waiting_visitor_gets_a_freed_ticket() {
+        let meseum = Arc::new(Meseum::new(1));
+        let ticket = meseum.get_ticket().unwrap();
+
+        let waiting = meseum.clone();
+        let mut visitor = tokio::spawn(async move { waiting.acquire_ticket().await });
+        let still_waiting = timeout(Duration::from_millis(10), &mut visitor).await;
+        assert!(still_waiting.is_err());
+
+        drop(ticket);
+        let _ticket = visitor.await.unwrap();
+        assert_eq!(meseum.tickets(), 0);
+    }
+
+    #[tokio::test]
+    async fn acquire_gives_up_after_timeout() {
+        let meseum = Meseum::new(1);
+        let ticket = meseum.get_ticket().unwrap();
+        let waited = meseum.acquire_ticket_timeout(Duration::from_millis(10));
+        assert!(waited.await.is_none());
+
+        // nothing leaked by the abandoned wait
+        drop(ticket);
+        assert_eq!(meseum.tickets(), 1);
+        let ticket = meseum.acquire_ticket_timeout(Duration::from_millis(10));
+        assert!(ticket.await.is_some());
+    }
+
+    #[tokio::test]
+    async fn unconfirmed_reservations_expire() {
+        let meseum = Meseum::new(1);
+        let reservation = meseum.reserve(Duration::from_millis(10)).unwrap();
+        assert_eq!(meseum.tickets(), 0);
+        assert!(meseum.reserve(Duration::from_millis(10)).is_none());
+
+        sleep(Duration::from_millis(30)).await;
+        assert!(reservation.is_expired());
+        assert_eq!(meseum.tickets(), 1);
+        let _ticket = meseum.get_ticket().unwrap();
+        assert!(reservation.confirm().is_none());
+    }
+
+    #[tokio::test]
+    async fn confirmed_reservations_become_tickets() {
+        let meseum = Meseum::new(1);
+        let reservation = meseum.reserve(Duration::from_millis(10)).unwrap();
+        let ticket = reservation.confirm().unwrap();
+        sleep(Duration::from_millis(30)).await;
+        assert_eq!(meseum.tickets(), 0);
+        drop(ticket);
+        assert_eq!(meseum.tickets(), 1);
+
+        drop(meseum.reserve(Duration::from_secs(60)));
+        assert_eq!(meseum.tickets(), 1);
+    }
+
+    #[test]
+    fn weighted_tickets_use_up_their_weight() {
+        let meseum = Meseum::new(10);
+        let bus = meseum.get_ticket_weighted(3).unwrap();
+        let van = meseum.get_ticket_weighted(4).unwrap();
+        assert_eq!((bus.weight(), van.weight()), (3, 4));
+        assert!(meseum.get_ticket_weighted(5).is_none());
+        assert_eq!(meseum.tickets(), 3);
+
+        drop(van);
+        assert_eq!(meseum.tickets(), 7);
+        let _coach = meseum.get_ticket_weighted(5).unwrap();
+        assert_eq!(meseum.tickets(), 2);
+        assert!(meseum.get_ticket_weighted(0).is_none());
+    }
+
+    #[test]
+    fn groups_get_all_or_nothing() {
+        let meseum = Meseum::new(10);
+        let group = meseum.get_group(7).unwrap();
+        assert_eq!(group.count(), 7);
+        assert_eq!(meseum.tickets(), 3);
+        assert!(meseum.get_group(5).is_none());
+        assert!(meseum.get_group(0).is_none());
+        assert_eq!(meseum.tickets(), 3);
+        drop(group);
+        assert_eq!(meseum.tickets(), 10);
+    }
+
+    #[test]
+    fn added_tickets_can_be_sold() {
+        let meseum = Meseum::new(2);
+        let _sold: Vec<_> = (0..2).map(|_| meseum.get_ticket().unwrap()).collect();
+        assert!(meseum.get_ticket().is_none());
+
+        meseum.add_tickets(3);
+        assert_eq!((meseum.tickets(), meseum.capacity()), (3, 5));
+        let _more: Vec<_> = (0..3).map(|_| meseum.get_ticket().unwrap()).collect();
+        assert!(meseum.get_ticket().is_none());
+    }
+
+    #[test]
+    fn get_up_to() {
+        let meseum = Meseum::new(3);
+        let bulk = meseum.get_up_to(5);
+        assert_eq!(bulk.count(), 3);
+        assert_eq!(meseum.tickets(), 0);
+        assert_eq!(meseum.get_up_to(2).count(), 0);
+        drop(bulk);
+        assert_eq!(meseum.tickets(), 3);
+
+        let bulk = meseum.get_up_to(2);
+        assert_eq!(bulk.count(), 2);
+        assert_eq!(meseum.tickets(), 1);
+    }
 }
@@ -0,0 +1,85 @@
+use anyhow::Result;
+
+use crate::{
+    actor::HandleCall,
+    ticket::{Meseum, Ticket},
+};
+
+pub struct TicketOffice {
+    meseum: Meseum,
+    held: Vec<Ticket>,
+}
+
+impl TicketOffice {
+    pub fn new(total: usize) -> Self {
+        Self {
+            meseum: Meseum::new(total),
+            held: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TicketRequest {
+    Acquire,
+    Release,
+    Count,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TicketReply {
+    Acquired(bool),
+    Released(bool),
+    Count(usize),
+}
+
+impl HandleCall for TicketOffice {
+    type Request = TicketRequest;
+    type Reply = TicketReply;
+
+    fn handle_call(&mut self, request: &Self::Request) -> Result<Self::Reply> {
+        let reply = match request {
+            TicketRequest::Acquire => match self.meseum.get_ticket() {
+                Some(ticket) => {
+                    self.held.push(ticket);
+                    TicketReply::Acquired(true)
+                }
+                None => TicketReply::Acquired(false),
+            },
+            TicketRequest::Release => TicketReply::Released(self.held.pop().is_some()),
+            TicketRequest::Count => TicketReply::Count(self.meseum.tickets()),
+        };
+        Ok(reply)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::actor::{Actor, Pid};
+
+    #[tokio::test]
+    async fn it_works() {
+        let p: Pid<TicketRequest, TicketReply> = Actor::spawn(10, TicketOffice::new(3)).unwrap();
+        assert_eq!(
+            p.send(TicketRequest::Acquire).await.unwrap(),
+            TicketReply::Acquired(true)
+        );
+        assert_eq!(
+            p.send(TicketRequest::Acquire).await.unwrap(),
+            TicketReply::Acquired(true)
+        );
+        assert_eq!(
+            p.send(TicketRequest::Count).await.unwrap(),
+            TicketReply::Count(1)
+        );
+        assert_eq!(
+            p.send(TicketRequest::Release).await.unwrap(),
+            TicketReply::Released(true)
+        );
+        assert_eq!(
+            p.send(TicketRequest::Count).await.unwrap(),
+            TicketReply::Count(2)
+        );
+    }
+}
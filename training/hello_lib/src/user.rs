@@ -1,24 +1,126 @@
-use anyhow::Result;
+use std::str::FromStr;
+
+use anyhow::{bail, Error, Result};
 use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Schema version written by this code, payloads without a version are v0.
+pub const USER_VERSION: u32 = 1;
+/// Oldest age `User::try_new` accepts.
+pub const MAX_AGE: u8 = 150;
+/// Largest payload `User::read_from` accepts, in bytes.
+pub const MAX_USER_LEN: usize = 64 * 1024;
 
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
 pub struct User {
+    #[serde(default)]
+    version: u32,
     pub name: String,
     age: u8,
     pub gender: Gender,
 }
 
 impl User {
+    /// Creates a user without validating it, see `try_new`.
     pub fn new(name: String, age: u8, gender: Gender) -> Self {
-        Self { name, age, gender }
+        Self {
+            version: USER_VERSION,
+            name,
+            age,
+            gender,
+        }
     }
 
-    pub fn to_string(&self) -> Result<String> {
+    /// Creates a user with a non-empty name and an age up to `MAX_AGE`.
+    pub fn try_new(name: String, age: u8, gender: Gender) -> Result<Self> {
+        if name.is_empty() {
+            bail!("user name is empty");
+        }
+        if age > MAX_AGE {
+            bail!("user age {} is over {}", age, MAX_AGE);
+        }
+        Ok(Self::new(name, age, gender))
+    }
+
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    pub fn age(&self) -> u8 {
+        self.age
+    }
+
+    pub fn gender(&self) -> &Gender {
+        &self.gender
+    }
+
+    // upgrade a deserialized user to USER_VERSION
+    fn migrate(mut self) -> Result<Self> {
+        if self.version > USER_VERSION {
+            bail!("unsupported user version {}", self.version);
+        }
+        if self.version == 0 {
+            // v0 -> v1: only the version tag was added
+            self.version = 1;
+        }
+        Ok(self)
+    }
+
+    pub fn to_json(&self) -> Result<String> {
         Ok(serde_json::to_string(self)?)
     }
 
-    pub fn from_str(data: &str) -> Result<Self> {
-        Ok(serde_json::from_str(data)?)
+    pub fn from_json(data: &str) -> Result<Self> {
+        serde_json::from_str::<Self>(data)?.migrate()
+    }
+
+    /// Same as `to_json`.
+    pub fn to_string(&self) -> Result<String> {
+        self.to_json()
+    }
+
+    pub fn to_yaml(&self) -> Result<String> {
+        Ok(serde_yaml::to_string(self)?)
+    }
+
+    pub fn from_yaml(data: &str) -> Result<Self> {
+        serde_yaml::from_str::<Self>(data)?.migrate()
+    }
+
+    pub fn to_bincode(&self) -> Result<Vec<u8>> {
+        Ok(bincode::serialize(self)?)
+    }
+
+    pub fn from_bincode(data: &[u8]) -> Result<Self> {
+        bincode::deserialize::<Self>(data)?.migrate()
+    }
+
+    /// Writes the user as JSON prefixed with its length as a big-endian u32.
+    pub async fn write_to<W: AsyncWrite + Unpin>(&self, w: &mut W) -> Result<()> {
+        let data = serde_json::to_vec(self)?;
+        w.write_u32(u32::try_from(data.len())?).await?;
+        w.write_all(&data).await?;
+        Ok(())
+    }
+
+    /// Reads a user written by `write_to`, payloads over `MAX_USER_LEN` are rejected.
+    pub async fn read_from<R: AsyncRead + Unpin>(r: &mut R) -> Result<Self> {
+        let len = r.read_u32().await? as usize;
+        if len > MAX_USER_LEN {
+            bail!("user payload of {} bytes is over {}", len, MAX_USER_LEN);
+        }
+        let mut data = vec![0; len];
+        r.read_exact(&mut data).await?;
+        serde_json::from_slice::<Self>(&data)?.migrate()
+    }
+}
+
+/// Parses JSON, see `User::from_json`.
+impl FromStr for User {
+    type Err = Error;
+
+    fn from_str(data: &str) -> Result<Self> {
+        Self::from_json(data)
     }
 }
 
@@ -46,4 +148,70 @@ mod tests {
         let u2 = User::from_str(s.as_str()).unwrap();
         assert_eq!(u, u2);
     }
+
+    #[test]
+    fn legacy_payload_is_upgraded() {
+        let u = User::new("lxb".into(), 18, Gender::Male);
+        let s = u.to_string().unwrap();
+        assert!(s.starts_with(r#"{"version":1,"#));
+
+        let legacy = r#"{"name":"lxb","age":18,"gender":"Male"}"#;
+        let u2 = User::from_str(legacy).unwrap();
+        assert_eq!(u2.version(), USER_VERSION);
+        assert_eq!(u2, u);
+
+        let future = r#"{"version":99,"name":"lxb","age":18,"gender":"Male"}"#;
+        assert!(User::from_str(future).is_err());
+    }
+
+    #[test]
+    fn try_new_validates() {
+        let u = User::try_new("lxb".into(), 150, Gender::Female).unwrap();
+        assert_eq!((u.age(), u.gender()), (150, &Gender::Female));
+
+        let err = User::try_new("".into(), 18, Gender::Male).unwrap_err();
+        assert_eq!(err.to_string(), "user name is empty");
+        let err = User::try_new("lxb".into(), 151, Gender::Male).unwrap_err();
+        assert_eq!(err.to_string(), "user age 151 is over 150");
+    }
+
+    #[test]
+    fn formats_roundtrip() {
+        let u = User::new("lxb".into(), 18, Gender::Male);
+        assert_eq!(User::from_json(&u.to_json().unwrap()).unwrap(), u);
+        assert_eq!(User::from_yaml(&u.to_yaml().unwrap()).unwrap(), u);
+        assert_eq!(User::from_bincode(&u.to_bincode().unwrap()).unwrap(), u);
+
+        assert!(User::from_yaml("name: [").is_err());
+        assert!(User::from_bincode(&[1, 2]).is_err());
+    }
+
+    #[tokio::test]
+    async fn stream_roundtrip() {
+        let (mut client, mut server) = tokio::io::duplex(16);
+        let writer = tokio::spawn(async move {
+            let u = User::new("lxb".into(), 18, Gender::Male);
+            u.write_to(&mut client).await.unwrap();
+            User::default().write_to(&mut client).await.unwrap();
+        });
+        let u = User::read_from(&mut server).await.unwrap();
+        assert_eq!(u, User::new("lxb".into(), 18, Gender::Male));
+        assert_eq!(User::read_from(&mut server).await.unwrap(), User::default());
+        writer.await.unwrap();
+        assert!(User::read_from(&mut server).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn oversized_payload_is_rejected() {
+        let mut data: &[u8] = &u32::MAX.to_be_bytes();
+        let err = User::read_from(&mut data).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "user payload of {} bytes is over {}",
+                u32::MAX,
+                MAX_USER_LEN
+            )
+        );
+    }
 }
@@ -0,0 +1,23 @@
+use anyhow::Result;
+use kv::{client::KvClient, pb::FrameConfig};
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    tracing_subscriber::fmt().init();
+
+    let addr = "127.0.0.1:8888";
+    // KV_FRAME_ENDIAN: length prefix byte order the server was started with
+    let frame = FrameConfig::from_env()?;
+    let client = KvClient::connect_with_frame_config(addr, 16, frame).await?;
+    // KV_AUTH_TOKEN: secret the server was started with, if any
+    if let Ok(token) = std::env::var("KV_AUTH_TOKEN") {
+        client.auth(&token).await?;
+    }
+
+    client.put("hello", b"world").await?;
+    println!("get hello: {:?}", client.get("hello").await?);
+    println!("get world: {:?}", client.get("world").await?);
+    println!("del hello: {:?}", client.del("hello").await?);
+
+    Ok(())
+}
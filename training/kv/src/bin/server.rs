@@ -0,0 +1,90 @@
+use std::{env, sync::Arc, time::Duration};
+
+use anyhow::{bail, Result};
+use kv::{
+    net,
+    pb::FrameConfig,
+    server::{self, ServerState, MAX_VALUE_SIZE, SWEEP_INTERVAL},
+};
+use tokio::{net::TcpListener, signal};
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    tracing_subscriber::fmt().init();
+
+    // KV_REPLAY_SIZE: number of recent changes replayed to new subscribers
+    let replay_size =
+        net::parse_var("KV_REPLAY_SIZE", env::var("KV_REPLAY_SIZE").ok())?.unwrap_or(0);
+    // KV_MAX_VALUE_SIZE: largest value accepted by puts, in bytes
+    let max_value_size = net::parse_var("KV_MAX_VALUE_SIZE", env::var("KV_MAX_VALUE_SIZE").ok())?
+        .unwrap_or(MAX_VALUE_SIZE);
+    // KV_WAL: log of the writes made since the last snapshot
+    let wal = env::var("KV_WAL").unwrap_or_else(|_| "kv.wal".into());
+    let mut state = ServerState::new()
+        .with_replay_size(replay_size)
+        .with_max_value_size(max_value_size)
+        .with_wal(&wal)?;
+    // KV_AUTH_TOKEN: secret clients must authenticate with
+    if let Ok(token) = env::var("KV_AUTH_TOKEN") {
+        state = state.with_auth_token(token);
+    }
+    // KV_MAX_CONNS: most connections served at once, at least 1
+    if let Some(n) = net::parse_var("KV_MAX_CONNS", env::var("KV_MAX_CONNS").ok())? {
+        if n == 0 {
+            bail!("KV_MAX_CONNS must be at least 1");
+        }
+        state = state.with_max_conns(n);
+    }
+    // KV_CAPACITY: most keys kept, least recently used ones are evicted, at
+    // least 1
+    if let Some(capacity) = net::parse_var("KV_CAPACITY", env::var("KV_CAPACITY").ok())? {
+        if capacity == 0 {
+            bail!("KV_CAPACITY must be at least 1");
+        }
+        state = state.with_capacity(capacity);
+    }
+    let state = Arc::new(state);
+    // KV_SNAPSHOT: file the store is loaded from at startup and saved to on
+    // shutdown
+    let snapshot = env::var("KV_SNAPSHOT").unwrap_or_else(|_| "kv.snapshot".into());
+    let n = state.load_snapshot(&snapshot)?;
+    info!("Loaded {} entries from {}", n, snapshot);
+    let n = state.replay_wal(&wal)?;
+    info!("Replayed {} writes from {}", n, wal);
+    // KV_SWEEP_MS: how often expired keys are purged in the background, at
+    // least 1
+    let mut sweep_period = SWEEP_INTERVAL;
+    if let Some(ms) = net::parse_var("KV_SWEEP_MS", env::var("KV_SWEEP_MS").ok())? {
+        if ms == 0 {
+            bail!("KV_SWEEP_MS must be at least 1");
+        }
+        sweep_period = Duration::from_millis(ms);
+    }
+    // cancelled on Ctrl-C, stops the sweeper and the accept loop
+    let shutdown = CancellationToken::new();
+    server::spawn_sweeper(state.clone(), sweep_period, shutdown.clone());
+    // first argument, or KV_ADDR: address to listen on
+    let addr = net::bind_addr(env::args().nth(1), env::var("KV_ADDR").ok())?;
+    let listener = TcpListener::bind(addr).await?;
+    let nodelay = net::nodelay();
+    // KV_FRAME_ENDIAN: length prefix byte order, big (default) or little
+    let frame = FrameConfig::from_env()?;
+
+    info!("Listening on {}", addr);
+
+    let token = shutdown.clone();
+    tokio::spawn(async move {
+        if signal::ctrl_c().await.is_ok() {
+            token.cancel();
+        }
+    });
+    let served = server::serve(listener, state.clone(), frame, nodelay, shutdown).await;
+
+    // saved however serving ended, an error mustn't lose the writes
+    let n = state.save_snapshot(&snapshot)?;
+    info!("Saved {} entries to {}", n, snapshot);
+    state.truncate_wal()?;
+    served
+}
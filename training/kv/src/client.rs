@@ -1,38 +1,678 @@
-mod pb;
-use std::convert::TryFrom;
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    net::SocketAddr,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU32, AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
-use anyhow::Result;
-use futures::{SinkExt, StreamExt};
-use pb::*;
-use tokio::net::TcpStream;
-use tokio_util::codec::LengthDelimitedCodec;
+use anyhow::{anyhow, bail, Result};
+use futures::{future, SinkExt, Stream, StreamExt};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, AsyncWriteExt},
+    net::{TcpStream, ToSocketAddrs},
+    sync::{oneshot, Mutex, Semaphore},
+    task::JoinHandle,
+    time::sleep,
+};
+use tokio_util::codec::FramedRead;
+use tracing::{info, warn};
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    tracing_subscriber::fmt().init();
+use crate::{net, pb::*};
 
-    let addr = "127.0.0.1:8888";
+type Writer = Pin<Box<dyn AsyncWrite + Send>>;
+// reply slots by request id, `None` once the connection is closed
+type Pending = Arc<std::sync::Mutex<Option<HashMap<u64, oneshot::Sender<Response>>>>>;
+
+/// How a `KvClient` re-dials a lost connection: at most `max_retries`
+/// attempts, waiting `initial` after the first failure and doubling the wait
+/// up to `max`.
+#[derive(Debug, Clone, Copy)]
+pub struct Backoff {
+    pub initial: Duration,
+    pub max: Duration,
+    pub max_retries: usize,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_millis(50),
+            max: Duration::from_secs(2),
+            max_retries: 5,
+        }
+    }
+}
+
+// one connection, the write half and the task dispatching replies
+struct Conn {
+    writer: Mutex<Writer>,
+    pending: Pending,
+    reader: JoinHandle<()>,
+}
+
+impl Conn {
+    fn new<S>(stream: S, frame: &FrameConfig) -> Self
+    where
+        S: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        let (reader, writer) = tokio::io::split(stream);
+        let mut stream = FramedRead::new(reader, frame.codec());
+        let pending: Pending = Arc::new(std::sync::Mutex::new(Some(HashMap::new())));
+
+        let replies = pending.clone();
+        let reader = tokio::spawn(async move {
+            while let Some(Ok(buf)) = stream.next().await {
+                let msg = match Response::try_from(buf) {
+                    Ok(msg) => msg,
+                    Err(e) => {
+                        warn!("Failed to decode response: {}", e);
+                        break;
+                    }
+                };
+                let sender = replies
+                    .lock()
+                    .unwrap()
+                    .as_mut()
+                    .and_then(|q| q.remove(&msg.id));
+                match sender {
+                    Some(sender) => {
+                        let _ = sender.send(msg);
+                    }
+                    None => warn!("Dropped a reply to unknown request {}", msg.id),
+                }
+            }
+            // wake up everyone still waiting, the connection is gone
+            replies.lock().unwrap().take();
+        });
+
+        Self {
+            writer: Mutex::new(Box::pin(writer)),
+            pending,
+            reader,
+        }
+    }
+
+    fn is_closed(&self) -> bool {
+        self.pending.lock().unwrap().is_none()
+    }
+}
+
+// closes the connection unless the write it guards completes, a failed or
+// cancelled write can leave half a frame on it
+struct WriteGuard<'a> {
+    pending: &'a Pending,
+    done: bool,
+}
+
+impl Drop for WriteGuard<'_> {
+    fn drop(&mut self) {
+        if !self.done {
+            self.pending.lock().unwrap().take();
+        }
+    }
+}
+
+impl Drop for Conn {
+    fn drop(&mut self) {
+        // the reader task holds half of the stream, stop it to close the
+        // connection
+        self.reader.abort();
+    }
+}
+
+/// A KV client over a single connection.
+///
+/// Concurrent calls are pipelined on the connection, each request is tagged
+/// with a fresh id and the reply carrying it goes to its caller. At most
+/// `max_in_flight` requests are outstanding at once, further calls wait for
+/// a reply to free a slot.
+///
+/// A client from `connect` re-dials a lost connection, see `Backoff`. A call
+/// that fails with it is retried once on the new connection only if sending
+/// it twice is harmless and answered the same: reads and `info`. Writes
+/// return the error, their request may have been applied before the reply
+/// was lost.
+pub struct KvClient {
+    conn: std::sync::Mutex<Arc<Conn>>,
+    frame: FrameConfig,
+    next_id: AtomicU64,
+    in_flight: Semaphore,
+    max_in_flight: usize,
+    // where to re-dial, only known for clients from `connect`
+    addr: Option<SocketAddr>,
+    backoff: Backoff,
+    // held while re-dialing, so a lost connection is replaced once
+    redial: Mutex<()>,
+    // sent again on a new connection, see `auth` and `select`
+    token: std::sync::Mutex<Option<String>>,
+    db: AtomicU32,
+}
+
+impl KvClient {
+    pub async fn connect(addr: impl ToSocketAddrs, max_in_flight: usize) -> Result<Self> {
+        Self::connect_with_frame_config(addr, max_in_flight, FrameConfig::default()).await
+    }
+
+    pub async fn connect_with_frame_config(
+        addr: impl ToSocketAddrs,
+        max_in_flight: usize,
+        frame: FrameConfig,
+    ) -> Result<Self> {
+        if max_in_flight == 0 {
+            bail!("max_in_flight must be at least 1");
+        }
+        let stream = TcpStream::connect(addr).await?;
+        net::configure(&stream, net::nodelay())?;
+        let addr = stream.peer_addr()?;
+        let mut client = Self::with_frame_config(stream, max_in_flight, frame);
+        client.addr = Some(addr);
+        Ok(client)
+    }
+
+    pub fn new<S>(stream: S, max_in_flight: usize) -> Self
+    where
+        S: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        Self::with_frame_config(stream, max_in_flight, FrameConfig::default())
+    }
+
+    /// Panics if `max_in_flight` is 0, no call could ever be sent.
+    pub fn with_frame_config<S>(stream: S, max_in_flight: usize, frame: FrameConfig) -> Self
+    where
+        S: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        assert!(max_in_flight > 0, "max_in_flight must be at least 1");
+        Self {
+            conn: std::sync::Mutex::new(Arc::new(Conn::new(stream, &frame))),
+            frame,
+            // 0 is left for untagged requests
+            next_id: AtomicU64::new(1),
+            in_flight: Semaphore::new(max_in_flight),
+            max_in_flight,
+            addr: None,
+            backoff: Backoff::default(),
+            redial: Mutex::new(()),
+            token: std::sync::Mutex::new(None),
+            db: AtomicU32::new(0),
+        }
+    }
+
+    /// Sets how a lost connection is re-dialed.
+    pub fn with_backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    fn conn(&self) -> Arc<Conn> {
+        self.conn.lock().unwrap().clone()
+    }
+
+    /// True once the connection has been closed, calls will fail from now on
+    /// unless the client can re-dial.
+    pub fn is_closed(&self) -> bool {
+        self.conn().is_closed()
+    }
+
+    pub async fn call(&self, req: Request) -> Result<Response> {
+        let _permit = self.in_flight.acquire().await?;
+        let mut replies = self.roundtrip(std::slice::from_ref(&req)).await?;
+        Ok(replies.pop().unwrap())
+    }
+
+    /// Sends the requests pipelined, each chunk of up to `max_in_flight`
+    /// requests in a single write, and returns the replies in order.
+    pub async fn send_batch(&self, reqs: &[Request]) -> Result<Vec<Response>> {
+        let mut responses = Vec::with_capacity(reqs.len());
+        for chunk in reqs.chunks(self.max_in_flight) {
+            let _permits = self.in_flight.acquire_many(chunk.len() as u32).await?;
+            responses.extend(self.roundtrip(chunk).await?);
+        }
+        Ok(responses)
+    }
+
+    /// Sends the requests back to back and returns their replies in request
+    /// order, whatever order the server answers in, see `send_batch`.
+    pub async fn pipeline(&self, reqs: Vec<Request>) -> Result<Vec<Response>> {
+        self.send_batch(&reqs).await
+    }
+
+    // sends the requests and waits for their replies, once more on a new
+    // connection if this one turns out to be gone and they can be resent
+    async fn roundtrip(&self, reqs: &[Request]) -> Result<Vec<Response>> {
+        let mut conn = self.conn();
+        // nothing was sent yet, any request can go on a new connection
+        if self.addr.is_some() && conn.is_closed() {
+            self.reconnect(&conn).await?;
+            conn = self.conn();
+        }
+        match self.roundtrip_on(&conn, reqs).await {
+            Err(e) if self.addr.is_some() && conn.is_closed() => {
+                warn!("Connection lost: {}", e);
+                self.reconnect(&conn).await?;
+                if !reqs.iter().all(is_retryable) {
+                    return Err(e);
+                }
+                self.roundtrip_on(&self.conn(), reqs).await
+            }
+            res => res,
+        }
+    }
+
+    async fn roundtrip_on(&self, conn: &Conn, reqs: &[Request]) -> Result<Vec<Response>> {
+        let replies = self.write(conn, reqs).await?;
+        future::join_all(replies)
+            .await
+            .into_iter()
+            .map(|reply| reply.map_err(|_| anyhow!("connection closed before reply")))
+            .collect()
+    }
+
+    // replaces the `dead` connection, unless another call already did
+    async fn reconnect(&self, dead: &Arc<Conn>) -> Result<()> {
+        let addr = match self.addr {
+            Some(addr) => addr,
+            None => bail!("connection closed"),
+        };
+        let _redial = self.redial.lock().await;
+        if !Arc::ptr_eq(&self.conn(), dead) {
+            return Ok(());
+        }
+        let mut delay = self.backoff.initial;
+        for attempt in 1..=self.backoff.max_retries {
+            match self.dial(addr).await {
+                Ok(conn) => {
+                    info!("Reconnected to {}", addr);
+                    *self.conn.lock().unwrap() = Arc::new(conn);
+                    return Ok(());
+                }
+                Err(e) => warn!("Reconnect {} to {} failed: {}", attempt, addr, e),
+            }
+            if attempt < self.backoff.max_retries {
+                sleep(delay).await;
+                delay = (delay * 2).min(self.backoff.max);
+            }
+        }
+        bail!("failed to reconnect to {}", addr)
+    }
+
+    async fn dial(&self, addr: SocketAddr) -> Result<Conn> {
+        let stream = TcpStream::connect(addr).await?;
+        net::configure(&stream, net::nodelay())?;
+        let conn = Conn::new(stream, &self.frame);
+        let token = self.token.lock().unwrap().clone();
+        if let Some(token) = token {
+            let mut replies = self
+                .roundtrip_on(&conn, &[Request::new_auth(&token)])
+                .await?;
+            let msg = replies.pop().unwrap();
+            if msg.status() != Ok(ResponseCode::Ok) {
+                bail!("auth failed with code {}", msg.code);
+            }
+        }
+        // a new connection starts on database 0
+        let db = self.db.load(Ordering::Relaxed);
+        if db != 0 {
+            let mut replies = self.roundtrip_on(&conn, &[Request::new_select(db)]).await?;
+            let msg = replies.pop().unwrap();
+            if msg.status() != Ok(ResponseCode::Ok) {
+                bail!("select {} failed with code {}", db, msg.code);
+            }
+        }
+        Ok(conn)
+    }
+
+    // tags the requests, queues a reply slot for each and writes them all at
+    // once
+    async fn write(
+        &self,
+        conn: &Conn,
+        reqs: &[Request],
+    ) -> Result<Vec<oneshot::Receiver<Response>>> {
+        let reqs: Vec<_> = reqs
+            .iter()
+            .map(|req| {
+                let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+                req.clone().with_id(id)
+            })
+            .collect();
+        let buf = self.frame.encode_batch(&reqs)?;
+        let mut writer = conn.writer.lock().await;
+        let mut receivers = Vec::with_capacity(reqs.len());
+        match conn.pending.lock().unwrap().as_mut() {
+            Some(q) => {
+                for req in &reqs {
+                    let (sender, receiver) = oneshot::channel();
+                    q.insert(req.id, sender);
+                    receivers.push(receiver);
+                }
+            }
+            None => bail!("connection closed"),
+        }
+        let mut guard = WriteGuard {
+            pending: &conn.pending,
+            done: false,
+        };
+        let written = match writer.write_all(&buf).await {
+            Ok(()) => writer.flush().await,
+            Err(e) => Err(e),
+        };
+        // a failed write means the connection is unusable
+        written?;
+        guard.done = true;
+        Ok(receivers)
+    }
+
+    /// Authenticates the connection, must come first on a server with a
+    /// token configured. The server closes the connection on a wrong token.
+    pub async fn auth(&self, token: &str) -> Result<()> {
+        let msg = self.call(Request::new_auth(token)).await?;
+        match msg.status() {
+            Ok(ResponseCode::Ok) => {
+                *self.token.lock().unwrap() = Some(token.to_owned());
+                Ok(())
+            }
+            _ => bail!("auth failed with code {}", msg.code),
+        }
+    }
+
+    /// Switches to database `db`, see `RequestSelect`. A connection
+    /// re-dialed later is switched too.
+    pub async fn select(&self, db: u32) -> Result<()> {
+        let msg = self.call(Request::new_select(db)).await?;
+        match msg.status() {
+            Ok(ResponseCode::Ok) => {
+                self.db.store(db, Ordering::Relaxed);
+                Ok(())
+            }
+            _ => bail!("select {} failed with code {}", db, msg.code),
+        }
+    }
+
+    /// The server's metrics by name, see `ServerState::info`.
+    pub async fn info(&self) -> Result<HashMap<String, u64>> {
+        let msg = self.call(Request::new_info()).await?;
+        if msg.status() != Ok(ResponseCode::Ok) {
+            bail!("info failed with code {}", msg.code);
+        }
+        String::from_utf8(msg.value)?
+            .lines()
+            .map(|line| match line.split_once(':') {
+                Some((name, value)) => Ok((name.to_owned(), value.parse()?)),
+                None => bail!("malformed info line {:?}", line),
+            })
+            .collect()
+    }
+
+    pub async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let msg = self.call(Request::new_get(key)).await?;
+        match msg.status() {
+            Ok(ResponseCode::Ok) => Ok(Some(msg.value)),
+            Ok(ResponseCode::NotFound) => Ok(None),
+            _ => bail!("get {} failed with code {}", key, msg.code),
+        }
+    }
+
+    /// Deletes `key`, returns whether it existed.
+    pub async fn del(&self, key: &str) -> Result<bool> {
+        let msg = self.call(Request::new_del(key)).await?;
+        match msg.status() {
+            Ok(ResponseCode::Ok) => Ok(true),
+            Ok(ResponseCode::NotFound) => Ok(false),
+            _ => bail!("del {} failed with code {}", key, msg.code),
+        }
+    }
+
+    /// Sets `key` only if it is missing, returns whether it was set.
+    pub async fn setnx(&self, key: &str, value: impl Into<Vec<u8>>) -> Result<bool> {
+        let msg = self.call(Request::new_setnx(key, value)).await?;
+        match msg.status() {
+            Ok(ResponseCode::Ok) => Ok(true),
+            Ok(ResponseCode::AlreadyExists) => Ok(false),
+            _ => bail!("setnx {} failed with code {}", key, msg.code),
+        }
+    }
+
+    /// Gets several keys in one round-trip, `None` for the missing ones.
+    pub async fn mget(&self, keys: &[&str]) -> Result<Vec<Option<Vec<u8>>>> {
+        let msg = self.call(Request::new_mget(keys)).await?;
+        if msg.status() != Ok(ResponseCode::Ok) || msg.entries.len() != keys.len() {
+            bail!("mget failed with code {}", msg.code);
+        }
+        msg.entries
+            .into_iter()
+            .map(|entry| match entry.status() {
+                Ok(ResponseCode::Ok) => Ok(Some(entry.value)),
+                Ok(ResponseCode::NotFound) => Ok(None),
+                _ => bail!("get {} failed with code {}", entry.key, entry.code),
+            })
+            .collect()
+    }
+
+    pub async fn put(&self, key: &str, value: impl Into<Vec<u8>>) -> Result<()> {
+        let msg = self.call(Request::new_put(key, value)).await?;
+        match msg.status() {
+            Ok(ResponseCode::Ok) => Ok(()),
+            _ => bail!("put {} failed with code {}", key, msg.code),
+        }
+    }
+}
+
+// whether sending `req` twice ends, and is answered, like sending it once;
+// a resent del would find its key gone and answer that it never existed
+fn is_retryable(req: &Request) -> bool {
+    matches!(
+        req.command,
+        Some(request::Command::Get(_))
+            | Some(request::Command::Mget(_))
+            | Some(request::Command::Scan(_))
+            | Some(request::Command::RangeScan(_))
+            | Some(request::Command::Info(_))
+    )
+}
+
+/// Streams the puts and deletes of keys starting with `prefix` made from now
+/// on, see `RequestWatch`.
+///
+/// A watch holds its own connection, it can't be pipelined with calls.
+pub async fn watch(
+    addr: impl ToSocketAddrs,
+    prefix: &str,
+) -> Result<impl Stream<Item = Result<Response>>> {
+    watch_with_frame_config(addr, prefix, FrameConfig::default()).await
+}
+
+/// `watch` over a connection framed with `frame`.
+pub async fn watch_with_frame_config(
+    addr: impl ToSocketAddrs,
+    prefix: &str,
+    frame: FrameConfig,
+) -> Result<impl Stream<Item = Result<Response>>> {
     let stream = TcpStream::connect(addr).await?;
-    let mut stream = LengthDelimitedCodec::builder()
-        .length_field_length(2)
-        .new_framed(stream);
+    net::configure(&stream, net::nodelay())?;
+    let mut stream = frame.framed(stream);
+    stream.send(Request::new_watch(prefix).into()).await?;
+    // the first reply acknowledges the subscription
+    let ack = match stream.next().await {
+        Some(buf) => Response::try_from(buf?)?,
+        None => bail!("connection closed before reply"),
+    };
+    if ack.status() != Ok(ResponseCode::Ok) {
+        bail!("watch {} failed with code {}", prefix, ack.code);
+    }
+    Ok(stream.map(|buf| Ok(Response::try_from(buf?)?)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+    use tokio::{
+        io::duplex,
+        sync::mpsc,
+        time::{sleep, timeout},
+    };
+
+    #[tokio::test]
+    async fn in_flight_requests_are_bounded() {
+        let (client_io, server_io) = duplex(4096);
+        let client = Arc::new(KvClient::new(client_io, 2));
 
-    let msg = Request::new_put("hello", b"world");
-    stream.send(msg.into()).await?;
+        // fake server, counts requests read but not yet answered
+        let outstanding = Arc::new(AtomicUsize::new(0));
+        let max_outstanding = Arc::new(AtomicUsize::new(0));
+        let (mut sink, mut stream) = FrameConfig::default().framed(server_io).split();
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let counter = outstanding.clone();
+        let max = max_outstanding.clone();
+        tokio::spawn(async move {
+            while let Some(Ok(buf)) = stream.next().await {
+                let n = counter.fetch_add(1, Ordering::SeqCst) + 1;
+                max.fetch_max(n, Ordering::SeqCst);
+                sender.send(Request::try_from(buf).unwrap()).unwrap();
+            }
+        });
+        let counter = outstanding.clone();
+        tokio::spawn(async move {
+            while let Some(req) = receiver.recv().await {
+                sleep(Duration::from_millis(10)).await;
+                let key = match req.command {
+                    Some(request::Command::Get(RequestGet { key })) => key,
+                    _ => unreachable!(),
+                };
+                counter.fetch_sub(1, Ordering::SeqCst);
+                let value = key.as_bytes().to_vec();
+                let res = Response {
+                    id: req.id,
+                    ..Response::new(key, value)
+                };
+                sink.send(res.into()).await.unwrap();
+            }
+        });
 
-    let msg = Request::new_get("hello");
-    stream.send(msg.into()).await?;
+        let tasks: Vec<_> = (0..10)
+            .map(|i| {
+                let client = client.clone();
+                tokio::spawn(async move { client.get(&format!("key{}", i)).await })
+            })
+            .collect();
+        for (i, task) in tasks.into_iter().enumerate() {
+            let value = task.await.unwrap().unwrap();
+            assert_eq!(value, Some(format!("key{}", i).into_bytes()));
+        }
+        assert_eq!(max_outstanding.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn send_batch_pipelines_requests() {
+        let (client_io, server_io) = duplex(4096);
+        let client = KvClient::new(client_io, 2);
+        let mut server = FrameConfig::default().framed(server_io);
+        tokio::spawn(async move {
+            while let Some(Ok(buf)) = server.next().await {
+                let req = Request::try_from(buf).unwrap();
+                let mut res = match req.command {
+                    Some(request::Command::Get(RequestGet { key })) => {
+                        let value = key.as_bytes().to_vec();
+                        Response::new(key, value)
+                    }
+                    _ => Response::default(),
+                };
+                res.id = req.id;
+                server.send(res.into()).await.unwrap();
+            }
+        });
 
-    let msg = Request::new_get("world");
-    stream.send(msg.into()).await?;
+        let reqs: Vec<_> = (0..5)
+            .map(|i| Request::new_get(&format!("key{}", i)))
+            .collect();
+        let replies = client.send_batch(&reqs).await.unwrap();
+        let keys: Vec<_> = replies.into_iter().map(|r| r.key).collect();
+        assert_eq!(keys, ["key0", "key1", "key2", "key3", "key4"]);
+        assert_eq!(client.get("hello").await.unwrap(), Some(b"hello".to_vec()));
+    }
 
-    let msg = Request::new_del("hello");
-    stream.send(msg.into()).await?;
+    #[tokio::test]
+    async fn pipeline_matches_out_of_order_replies() {
+        let (client_io, server_io) = duplex(4096);
+        let client = KvClient::new(client_io, 4);
+        // fake server, answers each batch of three in reverse, with the
+        // command name as value
+        let mut server = FrameConfig::default().framed(server_io);
+        tokio::spawn(async move {
+            let mut batch = Vec::new();
+            while let Some(Ok(buf)) = server.next().await {
+                batch.push(Request::try_from(buf).unwrap());
+                if batch.len() < 3 {
+                    continue;
+                }
+                for req in batch.drain(..).rev() {
+                    let (key, name) = match req.command {
+                        Some(request::Command::Put(RequestPut { key, .. })) => (key, "put"),
+                        Some(request::Command::Get(RequestGet { key })) => (key, "get"),
+                        Some(request::Command::Del(RequestDel { key })) => (key, "del"),
+                        _ => unreachable!(),
+                    };
+                    let res = Response {
+                        id: req.id,
+                        ..Response::new(key, name.into())
+                    };
+                    server.send(res.into()).await.unwrap();
+                }
+            }
+        });
 
-    while let Some(Ok(buf)) = stream.next().await {
-        let msg = Response::try_from(buf)?;
-        println!("Got msg: {:?}", msg);
+        let reqs = vec![
+            Request::new_put("a", b"1".to_vec()),
+            Request::new_get("b"),
+            Request::new_del("c"),
+        ];
+        let replies = client.pipeline(reqs).await.unwrap();
+        let replies: Vec<_> = replies
+            .into_iter()
+            .map(|r| (r.key, String::from_utf8(r.value).unwrap()))
+            .collect();
+        assert_eq!(
+            replies,
+            [
+                ("a".to_string(), "put".to_string()),
+                ("b".to_string(), "get".to_string()),
+                ("c".to_string(), "del".to_string())
+            ]
+        );
     }
 
-    Ok(())
+    #[tokio::test]
+    async fn cancelled_writes_close_the_connection() {
+        // nobody reads the other end, a large request can't be written whole
+        let (client_io, _server_io) = duplex(64);
+        let client = KvClient::new(client_io, 2);
+        let put = client.put("key", vec![0; 4096]);
+        assert!(timeout(Duration::from_millis(50), put).await.is_err());
+        assert!(client.is_closed());
+        assert!(client.get("key").await.is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "max_in_flight must be at least 1")]
+    fn zero_max_in_flight_is_rejected() {
+        let (client_io, _server_io) = duplex(64);
+        KvClient::new(client_io, 0);
+    }
+
+    #[tokio::test]
+    async fn closed_connection_fails_calls() {
+        let (client_io, server_io) = duplex(4096);
+        let client = KvClient::new(client_io, 2);
+        drop(server_io);
+        assert!(client.get("hello").await.is_err());
+        assert!(client.get("hello").await.is_err());
+    }
 }
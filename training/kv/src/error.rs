@@ -0,0 +1,143 @@
+use std::{error::Error, fmt, io};
+
+use crate::pb::ResponseCode;
+
+/// Errors a KV command can fail with, each maps to a response code. A
+/// missing key isn't an error, commands return `None` for it.
+#[derive(Debug)]
+pub enum KvError {
+    /// The request was malformed.
+    BadRequest(String),
+    /// A frame isn't a valid message.
+    Decode(prost::DecodeError),
+    /// Reading or writing the connection or a file failed.
+    Io(io::Error),
+    /// A conditional write found a different value.
+    Conflict,
+    /// A put-if-absent found the key set.
+    AlreadyExists,
+    /// The connection hasn't authenticated, or with a wrong token.
+    Unauthorized,
+    /// A value exceeds the server's size limit.
+    TooLarge,
+    /// The stored value isn't an 8-byte integer.
+    NotAnInteger,
+    /// The request carried no command, or one the server doesn't handle.
+    NotImplemented,
+    /// Something went wrong on the server side.
+    Internal(String),
+}
+
+impl KvError {
+    pub fn code(&self) -> ResponseCode {
+        match self {
+            KvError::BadRequest(_) | KvError::Decode(_) => ResponseCode::BadRequest,
+            KvError::Conflict => ResponseCode::Conflict,
+            KvError::AlreadyExists => ResponseCode::AlreadyExists,
+            KvError::Unauthorized => ResponseCode::Unauthorized,
+            KvError::TooLarge => ResponseCode::TooLarge,
+            KvError::NotAnInteger => ResponseCode::NotAnInteger,
+            KvError::NotImplemented => ResponseCode::NotImplemented,
+            KvError::Io(_) | KvError::Internal(_) => ResponseCode::Internal,
+        }
+    }
+}
+
+impl fmt::Display for KvError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            KvError::BadRequest(msg) => write!(f, "bad request: {}", msg),
+            KvError::Decode(e) => write!(f, "failed to decode: {}", e),
+            KvError::Io(e) => write!(f, "io error: {}", e),
+            KvError::Conflict => write!(f, "value does not match"),
+            KvError::AlreadyExists => write!(f, "key already exists"),
+            KvError::Unauthorized => write!(f, "unauthorized"),
+            KvError::TooLarge => write!(f, "value is too large"),
+            KvError::NotAnInteger => write!(f, "value is not an integer"),
+            KvError::NotImplemented => write!(f, "not implemented"),
+            KvError::Internal(msg) => write!(f, "internal error: {}", msg),
+        }
+    }
+}
+
+impl Error for KvError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            KvError::Io(e) => Some(e),
+            KvError::Decode(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+// io errors compare by kind and message, they aren't comparable themselves
+impl PartialEq for KvError {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (KvError::BadRequest(a), KvError::BadRequest(b))
+            | (KvError::Internal(a), KvError::Internal(b)) => a == b,
+            (KvError::Decode(a), KvError::Decode(b)) => a == b,
+            (KvError::Io(a), KvError::Io(b)) => {
+                a.kind() == b.kind() && a.to_string() == b.to_string()
+            }
+            (KvError::Conflict, KvError::Conflict)
+            | (KvError::AlreadyExists, KvError::AlreadyExists)
+            | (KvError::Unauthorized, KvError::Unauthorized)
+            | (KvError::TooLarge, KvError::TooLarge)
+            | (KvError::NotAnInteger, KvError::NotAnInteger)
+            | (KvError::NotImplemented, KvError::NotImplemented) => true,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for KvError {}
+
+impl From<prost::DecodeError> for KvError {
+    fn from(e: prost::DecodeError) -> Self {
+        KvError::Decode(e)
+    }
+}
+
+impl From<io::Error> for KvError {
+    fn from(e: io::Error) -> Self {
+        KvError::Io(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pb::{from_frame, Request};
+
+    #[test]
+    fn errors_display() {
+        assert_eq!(KvError::Conflict.to_string(), "value does not match");
+        assert_eq!(
+            KvError::BadRequest("empty key".into()).to_string(),
+            "bad request: empty key"
+        );
+        assert_eq!(KvError::NotImplemented.to_string(), "not implemented");
+        assert_eq!(
+            KvError::from(io::Error::new(io::ErrorKind::BrokenPipe, "broken pipe")).to_string(),
+            "io error: broken pipe"
+        );
+    }
+
+    #[test]
+    fn conversions_pick_the_variant() {
+        let err = from_frame::<Request>(b"\xff\xff\xff").unwrap_err();
+        assert!(matches!(err, KvError::Decode(_)));
+        assert!(err.to_string().starts_with("failed to decode: "));
+        assert!(err.source().unwrap().is::<prost::DecodeError>());
+        assert_eq!(err.code(), ResponseCode::BadRequest);
+
+        let err = KvError::from(io::Error::new(io::ErrorKind::BrokenPipe, "broken pipe"));
+        assert!(matches!(&err, KvError::Io(e) if e.kind() == io::ErrorKind::BrokenPipe));
+        assert_eq!(err.source().unwrap().to_string(), "broken pipe");
+        assert_eq!(err.code(), ResponseCode::Internal);
+        assert_eq!(KvError::Conflict.code(), ResponseCode::Conflict);
+        assert!(KvError::Conflict.source().is_none());
+        assert_eq!(KvError::NotImplemented.code(), ResponseCode::NotImplemented);
+    }
+}
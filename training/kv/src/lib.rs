@@ -0,0 +1,7 @@
+pub mod client;
+pub use hello_lib::clock;
+pub mod error;
+pub mod net;
+pub mod pb;
+pub mod pool;
+pub mod server;
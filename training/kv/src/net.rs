@@ -0,0 +1,89 @@
+use std::{env, io, net::SocketAddr, str::FromStr};
+
+use anyhow::{bail, Context, Result};
+use tokio::net::TcpStream;
+
+/// Address the server listens on when none is given.
+pub const DEFAULT_ADDR: &str = "0.0.0.0:8888";
+
+/// Whether to set `TCP_NODELAY` on KV connections, on by default so that small
+/// request/reply frames are not held back by Nagle's algorithm.
+/// Set `KV_NODELAY=0` to turn it off.
+pub fn nodelay() -> bool {
+    match env::var("KV_NODELAY") {
+        Ok(v) => v != "0" && !v.eq_ignore_ascii_case("false"),
+        Err(_) => true,
+    }
+}
+
+pub fn configure(stream: &TcpStream, nodelay: bool) -> io::Result<()> {
+    stream.set_nodelay(nodelay)
+}
+
+/// The server's listen address: `arg` (the first command line argument) if
+/// given, else `env` (`KV_ADDR`), else `DEFAULT_ADDR`.
+pub fn bind_addr(arg: Option<String>, env: Option<String>) -> Result<SocketAddr> {
+    let addr = arg.or(env).unwrap_or_else(|| DEFAULT_ADDR.into());
+    addr.parse()
+        .with_context(|| format!("invalid listen address {:?}", addr))
+}
+
+/// Parses `value`, the setting `name` read from the environment, if given; a
+/// value that doesn't parse is an error rather than silently ignored.
+pub fn parse_var<T: FromStr>(name: &str, value: Option<String>) -> Result<Option<T>> {
+    match value {
+        Some(v) => match v.parse() {
+            Ok(parsed) => Ok(Some(parsed)),
+            Err(_) => bail!("invalid {} {:?}", name, v),
+        },
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn bind_addr_resolution() {
+        let addr = bind_addr(Some("127.0.0.1:9000".into()), Some("127.0.0.1:9001".into()));
+        assert_eq!(addr.unwrap(), "127.0.0.1:9000".parse().unwrap());
+        let addr = bind_addr(None, Some("[::1]:9001".into()));
+        assert_eq!(addr.unwrap(), "[::1]:9001".parse().unwrap());
+        assert_eq!(
+            bind_addr(None, None).unwrap(),
+            DEFAULT_ADDR.parse().unwrap()
+        );
+
+        let err = bind_addr(Some("localhost".into()), None).unwrap_err();
+        assert_eq!(err.to_string(), "invalid listen address \"localhost\"");
+    }
+
+    #[test]
+    fn parse_var_rejects_garbage() {
+        assert_eq!(
+            parse_var::<usize>("KV_MAX_CONNS", Some("10".into())).unwrap(),
+            Some(10)
+        );
+        assert_eq!(parse_var::<usize>("KV_MAX_CONNS", None).unwrap(), None);
+        let err = parse_var::<usize>("KV_MAX_CONNS", Some("1O".into())).unwrap_err();
+        assert_eq!(err.to_string(), "invalid KV_MAX_CONNS \"1O\"");
+    }
+
+    #[tokio::test]
+    async fn it_works() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let client = TcpStream::connect(addr).await.unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+
+        configure(&client, true).unwrap();
+        configure(&server, true).unwrap();
+        assert!(client.nodelay().unwrap());
+        assert!(server.nodelay().unwrap());
+
+        configure(&client, false).unwrap();
+        assert!(!client.nodelay().unwrap());
+    }
+}
@@ -1,44 +1,217 @@
+use anyhow::bail;
 use bytes::{Bytes, BytesMut};
 use prost::Message;
-use std::convert::TryFrom;
+use std::{convert::TryFrom, env, fmt, io, str::FromStr, time::Duration};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_util::codec::{Encoder, Framed, LengthDelimitedCodec};
 
 mod abi;
 
 pub use abi::*;
 
+use crate::error::KvError;
+
 use self::request::Command;
 
+/// Size in bytes of the frame length prefix.
+pub const LENGTH_FIELD_LEN: usize = 4;
+
+/// Length-delimited framing options. Both ends of a connection must use the
+/// same settings, a mismatch garbles every frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameConfig {
+    /// Byte order of the length field, big-endian by default.
+    pub big_endian: bool,
+}
+
+impl Default for FrameConfig {
+    fn default() -> Self {
+        Self { big_endian: true }
+    }
+}
+
+/// Parses a byte order, `big` or `little`.
+impl FromStr for FrameConfig {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("big") {
+            Ok(Self { big_endian: true })
+        } else if s.eq_ignore_ascii_case("little") {
+            Ok(Self { big_endian: false })
+        } else {
+            bail!("invalid frame endianness {:?}, expected big or little", s)
+        }
+    }
+}
+
+impl FrameConfig {
+    /// Reads `KV_FRAME_ENDIAN` (`big` or `little`), the default if unset; any
+    /// other value is an error.
+    pub fn from_env() -> anyhow::Result<Self> {
+        match env::var("KV_FRAME_ENDIAN") {
+            Ok(v) => v.parse(),
+            Err(_) => Ok(Self::default()),
+        }
+    }
+
+    pub fn codec(&self) -> LengthDelimitedCodec {
+        let mut builder = LengthDelimitedCodec::builder();
+        builder.length_field_length(LENGTH_FIELD_LEN);
+        if !self.big_endian {
+            builder.little_endian();
+        }
+        builder.new_codec()
+    }
+
+    pub fn framed<T: AsyncRead + AsyncWrite>(&self, stream: T) -> Framed<T, LengthDelimitedCodec> {
+        Framed::new(stream, self.codec())
+    }
+
+    /// Frames every request into one buffer, ready to be written at once.
+    pub fn encode_batch(&self, reqs: &[Request]) -> io::Result<Bytes> {
+        let mut codec = self.codec();
+        let mut buf = BytesMut::new();
+        for req in reqs {
+            codec.encode(to_frame(req), &mut buf)?;
+        }
+        Ok(buf.freeze())
+    }
+}
+
+/// Typed view of `Response.code`, the wire format stays a plain i32.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResponseCode {
+    Ok = 0,
+    BadRequest = 400,
+    Unauthorized = 401,
+    NotFound = 404,
+    Conflict = 409,
+    AlreadyExists = 412,
+    TooLarge = 413,
+    NotAnInteger = 422,
+    Internal = 500,
+    /// The request carried no command, or one the server doesn't handle.
+    NotImplemented = 501,
+}
+
+impl ResponseCode {
+    const ALL: [ResponseCode; 10] = [
+        ResponseCode::Ok,
+        ResponseCode::BadRequest,
+        ResponseCode::Unauthorized,
+        ResponseCode::NotFound,
+        ResponseCode::Conflict,
+        ResponseCode::AlreadyExists,
+        ResponseCode::TooLarge,
+        ResponseCode::NotAnInteger,
+        ResponseCode::Internal,
+        ResponseCode::NotImplemented,
+    ];
+}
+
+impl From<ResponseCode> for i32 {
+    fn from(code: ResponseCode) -> Self {
+        code as i32
+    }
+}
+
+impl TryFrom<i32> for ResponseCode {
+    /// The unknown code.
+    type Error = i32;
+
+    fn try_from(code: i32) -> Result<Self, Self::Error> {
+        ResponseCode::ALL
+            .into_iter()
+            .find(|c| i32::from(*c) == code)
+            .ok_or(code)
+    }
+}
+
 impl Response {
+    /// The typed response code, or the raw code if it isn't a known one.
+    pub fn status(&self) -> Result<ResponseCode, i32> {
+        ResponseCode::try_from(self.code)
+    }
+
     pub fn new(key: String, value: Vec<u8>) -> Self {
         Self {
-            code: 0,
+            code: ResponseCode::Ok.into(),
             key,
             value,
+            ..Default::default()
+        }
+    }
+
+    /// An ok response carrying per-key results.
+    pub fn with_entries(entries: Vec<Response>) -> Self {
+        Self {
+            entries,
+            ..Default::default()
         }
     }
 
     pub fn not_found(key: String) -> Self {
         Self {
-            code: 404,
+            code: ResponseCode::NotFound.into(),
             key,
             ..Default::default()
         }
     }
 
+    /// Maps a command result to a response: a value, a 404 for a missing
+    /// key, or the error's code with its message as the value.
+    pub fn from_result(key: String, result: Result<Option<Vec<u8>>, KvError>) -> Self {
+        match result {
+            Ok(Some(value)) => Self::new(key, value),
+            Ok(None) => Self::not_found(key),
+            Err(e) => Self {
+                code: e.code().into(),
+                key,
+                value: e.to_string().into_bytes(),
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Answers a request the server doesn't implement.
     pub fn not_impl() -> Self {
         Self {
-            code: 500,
+            code: ResponseCode::NotImplemented.into(),
             ..Default::default()
         }
     }
 }
 
 impl Request {
+    /// Tags the request with `id`, echoed back in its response.
+    pub fn with_id(mut self, id: u64) -> Self {
+        self.id = id;
+        self
+    }
+
     pub fn new_get(key: &str) -> Self {
         Self {
             command: Some(Command::Get(RequestGet {
                 key: key.to_owned(),
             })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_auth(token: &str) -> Self {
+        Self {
+            command: Some(Command::Auth(RequestAuth {
+                token: token.to_owned(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_info() -> Self {
+        Self {
+            command: Some(Command::Info(RequestInfo {})),
+            ..Default::default()
         }
     }
 
@@ -47,47 +220,606 @@ impl Request {
             command: Some(Command::Del(RequestDel {
                 key: key.to_owned(),
             })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_expired_subscribe() -> Self {
+        Self {
+            command: Some(Command::ExpiredSubscribe(RequestExpiredSubscribe {})),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_subscribe(prefix: &str) -> Self {
+        Self {
+            command: Some(Command::Subscribe(RequestSubscribe {
+                prefix: prefix.to_owned(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_watch(prefix: &str) -> Self {
+        Self {
+            command: Some(Command::Watch(RequestWatch {
+                prefix: prefix.to_owned(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_put<V: Into<Vec<u8>>>(key: &str, value: V) -> Self {
+        Self {
+            command: Some(Command::Put(RequestPut {
+                key: key.to_owned(),
+                value: value.into(),
+                ttl_ms: 0,
+                compress: false,
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// A put of a value the server stores zstd-compressed.
+    pub fn new_put_compressed<V: Into<Vec<u8>>>(key: &str, value: V) -> Self {
+        Self {
+            command: Some(Command::Put(RequestPut {
+                key: key.to_owned(),
+                value: value.into(),
+                ttl_ms: 0,
+                compress: true,
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_setnx<V: Into<Vec<u8>>>(key: &str, value: V) -> Self {
+        Self {
+            command: Some(Command::Setnx(RequestSetNx {
+                key: key.to_owned(),
+                value: value.into(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_getset<V: Into<Vec<u8>>>(key: &str, value: V) -> Self {
+        Self {
+            command: Some(Command::Getset(RequestGetSet {
+                key: key.to_owned(),
+                value: value.into(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_rename(from: &str, to: &str) -> Self {
+        Self {
+            command: Some(Command::Rename(RequestRename {
+                from: from.to_owned(),
+                to: to.to_owned(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_select(db: u32) -> Self {
+        Self {
+            command: Some(Command::Select(RequestSelect { db })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_mget(keys: &[&str]) -> Self {
+        Self {
+            command: Some(Command::Mget(RequestMget {
+                keys: keys.iter().map(|k| k.to_string()).collect(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_scan(prefix: &str, limit: u32) -> Self {
+        Self {
+            command: Some(Command::Scan(RequestScan {
+                prefix: prefix.to_owned(),
+                limit,
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_range_scan(prefix: &str, min: i64, max: i64, limit: u32) -> Self {
+        Self {
+            command: Some(Command::RangeScan(RequestRangeScan {
+                prefix: prefix.to_owned(),
+                min,
+                max,
+                limit,
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_incr(key: &str, delta: i64) -> Self {
+        Self {
+            command: Some(Command::Incr(RequestIncr {
+                key: key.to_owned(),
+                delta,
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// An incr by `-delta`, which has no `i64` for `delta == i64::MIN`.
+    pub fn new_decr(key: &str, delta: i64) -> Result<Self, KvError> {
+        let delta = delta
+            .checked_neg()
+            .ok_or_else(|| KvError::BadRequest(format!("cannot decr by {}", delta)))?;
+        Ok(Self::new_incr(key, delta))
+    }
+
+    pub fn new_cas<E, N>(key: &str, expected: E, new: N) -> Self
+    where
+        E: Into<Vec<u8>>,
+        N: Into<Vec<u8>>,
+    {
+        Self {
+            command: Some(Command::Cas(RequestCas {
+                key: key.to_owned(),
+                expected: expected.into(),
+                new: new.into(),
+            })),
+            ..Default::default()
         }
     }
 
-    pub fn new_put(key: &str, value: &[u8]) -> Self {
+    /// A put that expires after `ttl`, rounded down to whole milliseconds
+    /// but never to 0, which would mean no expiry. A `ttl` past `u64::MAX`
+    /// milliseconds saturates rather than wrapping.
+    pub fn new_put_ttl<V: Into<Vec<u8>>>(key: &str, value: V, ttl: Duration) -> Self {
         Self {
             command: Some(Command::Put(RequestPut {
                 key: key.to_owned(),
-                value: value.to_vec(),
+                value: value.into(),
+                ttl_ms: u64::try_from(ttl.as_millis()).unwrap_or(u64::MAX).max(1),
+                compress: false,
             })),
+            ..Default::default()
+        }
+    }
+}
+
+/// Frames a stream with the default `FrameConfig`.
+pub fn framed<T: AsyncRead + AsyncWrite>(stream: T) -> Framed<T, LengthDelimitedCodec> {
+    FrameConfig::default().framed(stream)
+}
+
+/// Frames a batch with the default `FrameConfig`.
+pub fn encode_batch(reqs: &[Request]) -> io::Result<Bytes> {
+    FrameConfig::default().encode_batch(reqs)
+}
+
+/// Parses a redis-like command: `GET key`, `SET key value` or `DEL key`.
+///
+/// Verbs are case-insensitive. Arguments containing spaces can be wrapped in
+/// double quotes, `\"` and `\\` escape inside quotes.
+impl FromStr for Request {
+    type Err = KvError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let args = split_args(s)?;
+        let (verb, args) = match args.split_first() {
+            Some((verb, args)) => (verb.to_ascii_uppercase(), args),
+            None => return Err(bad_request("empty command")),
+        };
+        match (verb.as_str(), args) {
+            ("GET", [key]) => Ok(Request::new_get(key)),
+            ("SET", [key, value]) => Ok(Request::new_put(key, value.as_str())),
+            ("DEL", [key]) => Ok(Request::new_del(key)),
+            ("GET" | "SET" | "DEL", _) => Err(bad_request(format!(
+                "wrong number of arguments for {}",
+                verb
+            ))),
+            _ => Err(bad_request(format!("unknown command {}", verb))),
         }
     }
 }
 
+fn split_args(s: &str) -> Result<Vec<String>, KvError> {
+    let mut args = Vec::new();
+    let mut chars = s.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let Some(c) = chars.next() else {
+            return Ok(args);
+        };
+        let mut arg = String::new();
+        if c == '"' {
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => {
+                        arg.push(chars.next().ok_or_else(|| bad_request("unclosed quote"))?)
+                    }
+                    Some(c) => arg.push(c),
+                    None => return Err(bad_request("unclosed quote")),
+                }
+            }
+            if chars.peek().is_some_and(|c| !c.is_whitespace()) {
+                return Err(bad_request("expected space after closing quote"));
+            }
+        } else {
+            arg.push(c);
+            while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                arg.push(c);
+            }
+        }
+        args.push(arg);
+    }
+}
+
+fn bad_request(msg: impl Into<String>) -> KvError {
+    KvError::BadRequest(msg.into())
+}
+
+/// Encodes any prost message into a frame payload.
+pub fn to_frame<M: Message>(m: &M) -> Bytes {
+    let mut buf = BytesMut::with_capacity(m.encoded_len());
+    // encoding only fails when the buffer is too small, BytesMut grows as needed
+    m.encode(&mut buf).unwrap();
+    buf.freeze()
+}
+
+/// Decodes a frame payload into any prost message.
+pub fn from_frame<M: Message + Default>(buf: &[u8]) -> Result<M, KvError> {
+    Ok(M::decode(buf)?)
+}
+
 impl TryFrom<BytesMut> for Request {
-    type Error = prost::DecodeError;
+    type Error = KvError;
 
     fn try_from(buf: BytesMut) -> Result<Self, Self::Error> {
-        Message::decode(buf)
+        from_frame(&buf)
     }
 }
 
 impl TryFrom<BytesMut> for Response {
-    type Error = prost::DecodeError;
+    type Error = KvError;
 
     fn try_from(buf: BytesMut) -> Result<Self, Self::Error> {
-        Message::decode(buf)
+        from_frame(&buf)
     }
 }
 
 impl From<Response> for Bytes {
     fn from(msg: Response) -> Self {
-        let mut buf = BytesMut::new();
-        msg.encode(&mut buf).unwrap();
-        buf.freeze()
+        to_frame(&msg)
     }
 }
 
 impl From<Request> for Bytes {
     fn from(msg: Request) -> Self {
-        let mut buf = BytesMut::new();
-        msg.encode(&mut buf).unwrap();
-        buf.freeze()
+        to_frame(&msg)
+    }
+}
+
+/// Wraps a message for logging, values are shown as their byte count so
+/// secrets stored in the KV don't end up in the logs.
+pub struct Redacted<'a, T>(pub &'a T);
+
+struct ByteCount(usize);
+
+impl fmt::Debug for ByteCount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<{} bytes>", self.0)
+    }
+}
+
+impl fmt::Debug for Redacted<'_, Request> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.0.command {
+            Some(Command::Get(RequestGet { key })) => {
+                f.debug_struct("Get").field("key", key).finish()
+            }
+            Some(Command::Put(RequestPut {
+                key,
+                value,
+                ttl_ms,
+                compress,
+            })) => {
+                let mut s = f.debug_struct("Put");
+                s.field("key", key).field("value", &ByteCount(value.len()));
+                if *ttl_ms > 0 {
+                    s.field("ttl_ms", ttl_ms);
+                }
+                if *compress {
+                    s.field("compress", compress);
+                }
+                s.finish()
+            }
+            Some(Command::Del(RequestDel { key })) => {
+                f.debug_struct("Del").field("key", key).finish()
+            }
+            Some(Command::Setnx(RequestSetNx { key, value })) => f
+                .debug_struct("SetNx")
+                .field("key", key)
+                .field("value", &ByteCount(value.len()))
+                .finish(),
+            Some(Command::Getset(RequestGetSet { key, value })) => f
+                .debug_struct("GetSet")
+                .field("key", key)
+                .field("value", &ByteCount(value.len()))
+                .finish(),
+            Some(Command::Rename(RequestRename { from, to })) => f
+                .debug_struct("Rename")
+                .field("from", from)
+                .field("to", to)
+                .finish(),
+            Some(Command::Cas(RequestCas { key, expected, new })) => f
+                .debug_struct("Cas")
+                .field("key", key)
+                .field("expected", &ByteCount(expected.len()))
+                .field("new", &ByteCount(new.len()))
+                .finish(),
+            Some(Command::Incr(RequestIncr { key, delta })) => f
+                .debug_struct("Incr")
+                .field("key", key)
+                .field("delta", delta)
+                .finish(),
+            Some(Command::Scan(RequestScan { prefix, limit })) => f
+                .debug_struct("Scan")
+                .field("prefix", prefix)
+                .field("limit", limit)
+                .finish(),
+            Some(Command::RangeScan(RequestRangeScan {
+                prefix,
+                min,
+                max,
+                limit,
+            })) => f
+                .debug_struct("RangeScan")
+                .field("prefix", prefix)
+                .field("min", min)
+                .field("max", max)
+                .field("limit", limit)
+                .finish(),
+            Some(Command::Select(RequestSelect { db })) => {
+                f.debug_struct("Select").field("db", db).finish()
+            }
+            Some(Command::Mget(RequestMget { keys })) => {
+                f.debug_struct("Mget").field("keys", keys).finish()
+            }
+            Some(Command::Auth(RequestAuth { token })) => f
+                .debug_struct("Auth")
+                .field("token", &ByteCount(token.len()))
+                .finish(),
+            Some(Command::Info(_)) => f.write_str("Info"),
+            Some(Command::ExpiredSubscribe(_)) => f.write_str("ExpiredSubscribe"),
+            Some(Command::Subscribe(RequestSubscribe { prefix })) => {
+                f.debug_struct("Subscribe").field("prefix", prefix).finish()
+            }
+            Some(Command::Watch(RequestWatch { prefix })) => {
+                f.debug_struct("Watch").field("prefix", prefix).finish()
+            }
+            None => f.write_str("None"),
+        }
+    }
+}
+
+impl fmt::Debug for Redacted<'_, Response> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Response")
+            .field("code", &self.0.code)
+            .field("key", &self.0.key)
+            .field("value", &ByteCount(self.0.value.len()))
+            .field("entries", &self.0.entries.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn new_put_accepts_owned_and_borrowed_values() {
+        let from_slice = Request::new_put("hello", &b"world"[..]);
+        assert_eq!(Request::new_put("hello", b"world"), from_slice);
+        assert_eq!(Request::new_put("hello", "world".to_string()), from_slice);
+        assert_eq!(Request::new_put("hello", b"world".to_vec()), from_slice);
+    }
+
+    #[test]
+    fn new_decr_negates_delta() {
+        let req = Request::new_decr("n", 5).unwrap();
+        assert!(matches!(
+            req.command,
+            Some(Command::Incr(RequestIncr { delta: -5, .. }))
+        ));
+        let req = Request::new_decr("n", i64::MAX).unwrap();
+        assert!(matches!(
+            req.command,
+            Some(Command::Incr(RequestIncr { delta, .. })) if delta == -i64::MAX
+        ));
+        assert!(matches!(
+            Request::new_decr("n", i64::MIN),
+            Err(KvError::BadRequest(_))
+        ));
+    }
+
+    #[test]
+    fn new_put_ttl_sets_ttl_ms() {
+        let req = Request::new_put_ttl("hello", "world", Duration::from_millis(50));
+        match req.command {
+            Some(Command::Put(put)) => assert_eq!(put.ttl_ms, 50),
+            _ => unreachable!(),
+        }
+        let req = Request::new_put_ttl("hello", "world", Duration::from_micros(10));
+        assert!(matches!(
+            req.command,
+            Some(Command::Put(RequestPut { ttl_ms: 1, .. }))
+        ));
+        let huge = Duration::from_millis(u64::MAX) + Duration::from_millis(1);
+        let req = Request::new_put_ttl("hello", "world", huge);
+        assert!(matches!(
+            req.command,
+            Some(Command::Put(RequestPut {
+                ttl_ms: u64::MAX,
+                ..
+            }))
+        ));
+        assert!(matches!(
+            Request::new_put("hello", "world").command,
+            Some(Command::Put(RequestPut { ttl_ms: 0, .. }))
+        ));
+    }
+
+    #[test]
+    fn response_from_result() {
+        let res = Response::from_result("hello".into(), Ok(Some(b"world".to_vec())));
+        assert_eq!(res, Response::new("hello".into(), b"world".to_vec()));
+
+        let res = Response::from_result("hello".into(), Ok(None));
+        assert_eq!(res, Response::not_found("hello".into()));
+
+        let res = Response::from_result("".into(), Err(KvError::NotImplemented));
+        assert_eq!(res.code, Response::not_impl().code);
+        let err = KvError::BadRequest("empty key".into());
+        let res = Response::from_result("".into(), Err(err));
+        assert_eq!(res.code, 400);
+        assert_eq!(res.value, b"bad request: empty key");
+        let res = Response::from_result("hello".into(), Err(KvError::Internal("oops".into())));
+        assert_eq!((res.code, res.key.as_str()), (500, "hello"));
+    }
+
+    #[test]
+    fn response_code_roundtrip() {
+        for code in ResponseCode::ALL {
+            let raw = i32::from(code);
+            assert_eq!(ResponseCode::try_from(raw), Ok(code));
+        }
+        assert_eq!(i32::from(ResponseCode::NotFound), 404);
+        assert_eq!(ResponseCode::try_from(418), Err(418));
+        assert_eq!(
+            Response::not_found("k".into()).status(),
+            Ok(ResponseCode::NotFound)
+        );
+        assert_eq!(
+            Response::not_impl().status(),
+            Ok(ResponseCode::NotImplemented)
+        );
+        assert_eq!(Response::not_impl().code, 501);
+    }
+
+    #[test]
+    fn parse_redis_like_commands() {
+        let req: Request = "GET hello".parse().unwrap();
+        assert_eq!(req, Request::new_get("hello"));
+        let req: Request = "set hello world".parse().unwrap();
+        assert_eq!(req, Request::new_put("hello", "world"));
+        let req: Request = "  Del   hello ".parse().unwrap();
+        assert_eq!(req, Request::new_del("hello"));
+        let req: Request = r#"SET "my key" "hello \"big\" world""#.parse().unwrap();
+        assert_eq!(req, Request::new_put("my key", r#"hello "big" world"#));
+        let req: Request = r#"SET empty """#.parse().unwrap();
+        assert_eq!(req, Request::new_put("empty", ""));
+    }
+
+    #[test]
+    fn parse_rejects_bad_commands() {
+        let err = "INCR counter".parse::<Request>().unwrap_err();
+        assert_eq!(err, KvError::BadRequest("unknown command INCR".into()));
+        assert!("GET".parse::<Request>().is_err());
+        assert!("SET hello".parse::<Request>().is_err());
+        assert!(r#"SET hello "world"#.parse::<Request>().is_err());
+        assert!("".parse::<Request>().is_err());
+    }
+
+    #[test]
+    fn generic_frame_roundtrip() {
+        let req = Request::new_put("hello", "world");
+        let buf = to_frame(&req);
+        assert_eq!(from_frame::<Request>(&buf).unwrap(), req);
+        assert_eq!(Bytes::from(req.clone()), buf);
+
+        let res = Response::new("hello".into(), b"world".to_vec());
+        let buf = to_frame(&res);
+        assert_eq!(from_frame::<Response>(&buf).unwrap(), res);
+        assert_eq!(Bytes::from(res), buf);
+    }
+
+    #[tokio::test]
+    async fn encode_batch_roundtrip() {
+        use futures::StreamExt;
+        use tokio_util::codec::FramedRead;
+
+        let reqs = vec![
+            Request::new_put("hello", "world"),
+            Request::new_get("hello"),
+            Request::new_del("hello"),
+        ];
+        for config in [FrameConfig::default(), FrameConfig { big_endian: false }] {
+            let buf = config.encode_batch(&reqs).unwrap();
+            let decoded: Vec<_> = FramedRead::new(&buf[..], config.codec())
+                .map(|frame| Request::try_from(frame.unwrap()).unwrap())
+                .collect()
+                .await;
+            assert_eq!(decoded, reqs);
+        }
+        assert_eq!(
+            encode_batch(&reqs).unwrap(),
+            FrameConfig::default().encode_batch(&reqs).unwrap()
+        );
+    }
+
+    #[test]
+    fn parse_frame_endianness() {
+        assert_eq!(
+            "big".parse::<FrameConfig>().unwrap(),
+            FrameConfig::default()
+        );
+        let config: FrameConfig = "Little".parse().unwrap();
+        assert!(!config.big_endian);
+        for bad in ["litle", "LE", ""] {
+            assert!(bad.parse::<FrameConfig>().is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn little_endian_framing_roundtrip() {
+        use futures::{SinkExt, StreamExt};
+        use tokio::io::AsyncReadExt;
+
+        let config = FrameConfig { big_endian: false };
+        let (client, server) = tokio::io::duplex(1024);
+        let mut client = config.framed(client);
+        let mut server = config.framed(server);
+
+        let msg = Request::new_put("hello", "world");
+        client.send(msg.clone().into()).await.unwrap();
+        let buf = server.next().await.unwrap().unwrap();
+        assert_eq!(Request::try_from(buf).unwrap(), msg);
+
+        // the length prefix on the wire is little-endian
+        let (client, mut raw) = tokio::io::duplex(1024);
+        let mut client = config.framed(client);
+        client.send(Bytes::from_static(b"abc")).await.unwrap();
+        let mut buf = [0u8; 7];
+        raw.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"\x03\x00\x00\x00abc");
+    }
+
+    #[test]
+    fn redacted_hides_values() {
+        let msg = Request::new_put("password", "hunter2");
+        let s = format!("{:?}", Redacted(&msg));
+        assert_eq!(s, r#"Put { key: "password", value: <7 bytes> }"#);
+
+        let msg = Response::new("password".into(), b"hunter2".to_vec());
+        let s = format!("{:?}", Redacted(&msg));
+        assert!(s.contains("<7 bytes>"));
+        assert!(!s.contains("hunter2") && !s.contains("104"));
     }
 }
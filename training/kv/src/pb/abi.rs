@@ -1,6 +1,9 @@
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct Request {
-    #[prost(oneof="request::Command", tags="1, 2, 3")]
+    /// chosen by the client, echoed in the response
+    #[prost(uint64, tag="10")]
+    pub id: u64,
+    #[prost(oneof="request::Command", tags="1, 2, 3, 4, 5, 6, 7, 8, 9, 11, 12, 13, 14, 15, 16, 18, 19")]
     pub command: ::core::option::Option<request::Command>,
 }
 /// Nested message and enum types in `Request`.
@@ -13,6 +16,34 @@ pub mod request {
         Put(super::RequestPut),
         #[prost(message, tag="3")]
         Del(super::RequestDel),
+        #[prost(message, tag="4")]
+        ExpiredSubscribe(super::RequestExpiredSubscribe),
+        #[prost(message, tag="5")]
+        Subscribe(super::RequestSubscribe),
+        #[prost(message, tag="6")]
+        Cas(super::RequestCas),
+        #[prost(message, tag="7")]
+        Incr(super::RequestIncr),
+        #[prost(message, tag="8")]
+        Scan(super::RequestScan),
+        #[prost(message, tag="9")]
+        Mget(super::RequestMget),
+        #[prost(message, tag="11")]
+        Auth(super::RequestAuth),
+        #[prost(message, tag="12")]
+        Info(super::RequestInfo),
+        #[prost(message, tag="13")]
+        Setnx(super::RequestSetNx),
+        #[prost(message, tag="14")]
+        Getset(super::RequestGetSet),
+        #[prost(message, tag="15")]
+        Rename(super::RequestRename),
+        #[prost(message, tag="16")]
+        RangeScan(super::RequestRangeScan),
+        #[prost(message, tag="18")]
+        Select(super::RequestSelect),
+        #[prost(message, tag="19")]
+        Watch(super::RequestWatch),
     }
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
@@ -23,21 +54,163 @@ pub struct Response {
     pub key: ::prost::alloc::string::String,
     #[prost(bytes="vec", tag="3")]
     pub value: ::prost::alloc::vec::Vec<u8>,
+    /// per-key results of multi-key commands
+    #[prost(message, repeated, tag="4")]
+    pub entries: ::prost::alloc::vec::Vec<Response>,
+    /// id of the request answered, 0 for pushed events
+    #[prost(uint64, tag="5")]
+    pub id: u64,
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct RequestGet {
     #[prost(string, tag="1")]
     pub key: ::prost::alloc::string::String,
 }
+/// must be the first request when the server has a token configured
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct RequestAuth {
+    #[prost(string, tag="1")]
+    pub token: ::prost::alloc::string::String,
+}
+/// switch the connection to database db, all connections start on 0
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct RequestSelect {
+    #[prost(uint32, tag="1")]
+    pub db: u32,
+}
+/// server metrics, answered as `name:value` lines of unsigned integers
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct RequestInfo {
+}
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct RequestPut {
     #[prost(string, tag="1")]
     pub key: ::prost::alloc::string::String,
     #[prost(bytes="vec", tag="2")]
     pub value: ::prost::alloc::vec::Vec<u8>,
+    /// expire the key after this many milliseconds, 0 means never
+    #[prost(uint64, tag="3")]
+    pub ttl_ms: u64,
+    /// store the value zstd-compressed, gets still return it as put
+    #[prost(bool, tag="4")]
+    pub compress: bool,
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct RequestDel {
     #[prost(string, tag="1")]
     pub key: ::prost::alloc::string::String,
 }
+/// set key to new only if its value is expected, a missing key matches empty
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct RequestCas {
+    #[prost(string, tag="1")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(bytes="vec", tag="2")]
+    pub expected: ::prost::alloc::vec::Vec<u8>,
+    #[prost(bytes="vec", tag="3")]
+    pub new: ::prost::alloc::vec::Vec<u8>,
+}
+/// set key only if it is missing, an existing key is left alone
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct RequestSetNx {
+    #[prost(string, tag="1")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(bytes="vec", tag="2")]
+    pub value: ::prost::alloc::vec::Vec<u8>,
+}
+/// set key and answer with the value it replaced, not found if it was missing
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct RequestGetSet {
+    #[prost(string, tag="1")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(bytes="vec", tag="2")]
+    pub value: ::prost::alloc::vec::Vec<u8>,
+}
+/// move the value and ttl of from to to, overwriting to if it exists
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct RequestRename {
+    #[prost(string, tag="1")]
+    pub from: ::prost::alloc::string::String,
+    #[prost(string, tag="2")]
+    pub to: ::prost::alloc::string::String,
+}
+/// live keys starting with prefix in ascending order, with their values, at
+/// most limit of them, 0 means no limit
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct RequestScan {
+    #[prost(string, tag="1")]
+    pub prefix: ::prost::alloc::string::String,
+    #[prost(uint32, tag="2")]
+    pub limit: u32,
+}
+/// live keys starting with prefix whose trailing integer is within
+/// [min, max], as entries without values in ascending numeric order, at most
+/// limit of them, 0 means no limit; keys without one are skipped
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct RequestRangeScan {
+    #[prost(string, tag="1")]
+    pub prefix: ::prost::alloc::string::String,
+    #[prost(sint64, tag="2")]
+    pub min: i64,
+    #[prost(sint64, tag="3")]
+    pub max: i64,
+    #[prost(uint32, tag="4")]
+    pub limit: u32,
+}
+/// one entry per key, in request order, each with its own code
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct RequestMget {
+    #[prost(string, repeated, tag="1")]
+    pub keys: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+}
+/// add delta to the little-endian i64 stored at key, a missing key counts as 0
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct RequestIncr {
+    #[prost(string, tag="1")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(sint64, tag="2")]
+    pub delta: i64,
+}
+/// stream the names of keys removed because their ttl expired
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct RequestExpiredSubscribe {
+}
+/// stream puts (code 0) and deletes (code 404) of keys starting with prefix
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct RequestSubscribe {
+    #[prost(string, tag="1")]
+    pub prefix: ::prost::alloc::string::String,
+}
+/// like subscribe, but only changes made from now on are streamed
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct RequestWatch {
+    #[prost(string, tag="1")]
+    pub prefix: ::prost::alloc::string::String,
+}
+/// one mutation logged in the wal file, applied to database db; a put is
+/// logged without a ttl and expires_at_ms is its expiry in unix time, 0 if
+/// none, so downtime doesn't extend it
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct WalRecord {
+    #[prost(uint32, tag="1")]
+    pub db: u32,
+    #[prost(message, optional, tag="2")]
+    pub request: ::core::option::Option<Request>,
+    #[prost(uint64, tag="3")]
+    pub expires_at_ms: u64,
+}
+/// one stored key in a snapshot file, ttl_ms is the time left, 0 if none
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct SnapshotEntry {
+    #[prost(string, tag="1")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(bytes="vec", tag="2")]
+    pub value: ::prost::alloc::vec::Vec<u8>,
+    #[prost(uint64, tag="3")]
+    pub ttl_ms: u64,
+    /// the value is saved zstd-compressed
+    #[prost(bool, tag="4")]
+    pub compressed: bool,
+    #[prost(uint32, tag="5")]
+    pub db: u32,
+}
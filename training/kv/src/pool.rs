@@ -0,0 +1,191 @@
+use std::{ops::Deref, sync::Mutex};
+
+use anyhow::Result;
+use futures::future::BoxFuture;
+use tokio::{
+    net::ToSocketAddrs,
+    sync::{Semaphore, SemaphorePermit},
+};
+
+use crate::client::KvClient;
+
+type Connector = Box<dyn Fn() -> BoxFuture<'static, Result<KvClient>> + Send + Sync>;
+
+/// A fixed-size pool of `KvClient` connections.
+///
+/// `get` waits until one of the `size` slots is free, reuses an idle
+/// connection if there is one and opens a new one otherwise. Connections
+/// found closed are dropped and replaced.
+pub struct KvPool {
+    connector: Connector,
+    idle: Mutex<Vec<KvClient>>,
+    slots: Semaphore,
+}
+
+impl KvPool {
+    pub fn connect<A>(addr: A, size: usize, max_in_flight: usize) -> Self
+    where
+        A: ToSocketAddrs + Clone + Send + Sync + 'static,
+    {
+        Self::with_connector(size, move || {
+            let addr = addr.clone();
+            Box::pin(async move { KvClient::connect(addr, max_in_flight).await })
+        })
+    }
+
+    pub fn with_connector<F>(size: usize, connector: F) -> Self
+    where
+        F: Fn() -> BoxFuture<'static, Result<KvClient>> + Send + Sync + 'static,
+    {
+        Self {
+            connector: Box::new(connector),
+            idle: Mutex::new(Vec::with_capacity(size)),
+            slots: Semaphore::new(size),
+        }
+    }
+
+    pub async fn get(&self) -> Result<PooledClient<'_>> {
+        let permit = self.slots.acquire().await?;
+        let reused = loop {
+            match self.idle.lock().unwrap().pop() {
+                Some(client) if client.is_closed() => continue,
+                other => break other,
+            }
+        };
+        let client = match reused {
+            Some(client) => client,
+            None => (self.connector)().await?,
+        };
+        Ok(PooledClient {
+            client: Some(client),
+            pool: self,
+            _permit: permit,
+        })
+    }
+
+    /// Number of idle connections kept in the pool.
+    pub fn idle(&self) -> usize {
+        self.idle.lock().unwrap().len()
+    }
+}
+
+/// A connection borrowed from a `KvPool`, handed back when dropped.
+pub struct PooledClient<'a> {
+    client: Option<KvClient>,
+    pool: &'a KvPool,
+    _permit: SemaphorePermit<'a>,
+}
+
+impl Deref for PooledClient<'_> {
+    type Target = KvClient;
+
+    fn deref(&self) -> &KvClient {
+        self.client.as_ref().unwrap()
+    }
+}
+
+impl Drop for PooledClient<'_> {
+    fn drop(&mut self) {
+        if let Some(client) = self.client.take() {
+            if !client.is_closed() {
+                self.pool.idle.lock().unwrap().push(client);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pb::{request::Command, *};
+    use futures::{SinkExt, StreamExt};
+    use std::{
+        convert::TryFrom,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+    };
+    use tokio::io::{duplex, DuplexStream};
+
+    // answers gets with the key as value, everything else with an empty ok
+    async fn echo_server(io: DuplexStream) {
+        let mut stream = FrameConfig::default().framed(io);
+        while let Some(Ok(buf)) = stream.next().await {
+            let req = Request::try_from(buf).unwrap();
+            let mut res = match req.command {
+                Some(Command::Get(RequestGet { key })) => {
+                    let value = key.as_bytes().to_vec();
+                    Response::new(key, value)
+                }
+                _ => Response::default(),
+            };
+            res.id = req.id;
+            if stream.send(res.into()).await.is_err() {
+                break;
+            }
+        }
+    }
+
+    fn counting_pool(size: usize, opened: Arc<AtomicUsize>) -> KvPool {
+        KvPool::with_connector(size, move || {
+            opened.fetch_add(1, Ordering::SeqCst);
+            Box::pin(async {
+                let (client_io, server_io) = duplex(4096);
+                tokio::spawn(echo_server(server_io));
+                Ok(KvClient::new(client_io, 4))
+            })
+        })
+    }
+
+    #[tokio::test]
+    async fn connections_are_reused() {
+        let opened = Arc::new(AtomicUsize::new(0));
+        let pool = Arc::new(counting_pool(2, opened.clone()));
+
+        let tasks: Vec<_> = (0..50)
+            .map(|i| {
+                let pool = pool.clone();
+                tokio::spawn(async move {
+                    let client = pool.get().await?;
+                    client.get(&format!("key{}", i)).await
+                })
+            })
+            .collect();
+        for (i, task) in tasks.into_iter().enumerate() {
+            let value = task.await.unwrap().unwrap();
+            assert_eq!(value, Some(format!("key{}", i).into_bytes()));
+        }
+        assert!(opened.load(Ordering::SeqCst) <= 2);
+        assert_eq!(pool.idle(), opened.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn dead_connections_are_replaced() {
+        let opened = Arc::new(AtomicUsize::new(0));
+        let counter = opened.clone();
+        let pool = KvPool::with_connector(1, move || {
+            let n = counter.fetch_add(1, Ordering::SeqCst);
+            Box::pin(async move {
+                let (client_io, server_io) = duplex(4096);
+                if n == 0 {
+                    // the first connection dies right away
+                    drop(server_io);
+                } else {
+                    tokio::spawn(echo_server(server_io));
+                }
+                Ok(KvClient::new(client_io, 4))
+            })
+        });
+
+        {
+            let client = pool.get().await.unwrap();
+            assert!(client.get("hello").await.is_err());
+        }
+        assert_eq!(pool.idle(), 0);
+
+        let client = pool.get().await.unwrap();
+        assert_eq!(client.get("hello").await.unwrap(), Some(b"hello".to_vec()));
+        assert_eq!(opened.load(Ordering::SeqCst), 2);
+    }
+}
@@ -1,19 +1,232 @@
-mod pb;
-use std::sync::Arc;
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::{BTreeMap, BinaryHeap, HashMap, VecDeque},
+    convert::TryFrom,
+    fs::{self, File, OpenOptions},
+    hash::Hash,
+    io::{self, Write},
+    path::Path,
+    sync::{
+        atomic::{self, AtomicU64, AtomicUsize},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant, UNIX_EPOCH},
+};
 
-use dashmap::DashMap;
-use pb::{request::*, *};
-
-use anyhow::Result;
+use anyhow::{bail, Result};
+use bytes::{Buf, BufMut, BytesMut};
+use dashmap::{
+    mapref::{entry::Entry, one::RefMut},
+    DashMap, SharedValue,
+};
 use futures::{SinkExt, StreamExt};
-use std::convert::TryInto;
-use tokio::net::TcpListener;
-use tokio_util::codec::length_delimited::LengthDelimitedCodec;
-use tracing::info;
+use prost::Message;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpListener,
+    sync::{broadcast, Notify, Semaphore},
+    task::JoinHandle,
+    time::timeout,
+};
+use tokio_util::{
+    codec::{Framed, LengthDelimitedCodec},
+    sync::CancellationToken,
+};
+use tracing::{error, info, warn};
+
+use crate::{
+    clock::{Clock, SystemClock},
+    error::KvError,
+    net,
+    pb::{request::*, *},
+};
+
+const EXPIRED_CHANNEL_SIZE: usize = 128;
+const CHANGE_CHANNEL_SIZE: usize = 128;
+/// Default limit on the size of a stored value.
+pub const MAX_VALUE_SIZE: usize = 1 << 20;
+/// Default period of the expired-key sweeper.
+pub const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+/// How long `serve` waits for open connections once shut down.
+pub const DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+/// Default limit on the connections `serve` handles at once.
+pub const MAX_CONNS: usize = 1024;
+/// Number of databases, see `RequestSelect`.
+pub const DATABASES: usize = 16;
+
+// snapshot file: magic, u16 version, length-delimited `SnapshotEntry`s, then a
+// crc32 of everything before it, all big-endian
+const SNAPSHOT_MAGIC: &[u8; 4] = b"KVSN";
+const SNAPSHOT_VERSION: u16 = 1;
+const SNAPSHOT_HEADER_LEN: usize = SNAPSHOT_MAGIC.len() + 2;
+const SNAPSHOT_CHECKSUM_LEN: usize = 4;
+
+const COMPRESSION_LEVEL: i32 = 3;
 
 #[derive(Debug)]
-struct ServerState {
-    store: DashMap<String, Vec<u8>>,
+pub struct ServerState {
+    // one store per database, of values and optional expiry deadlines
+    dbs: Vec<DashMap<String, (Stored, Option<Instant>)>>,
+    clock: Arc<dyn Clock>,
+    // database and name of keys removed because they expired
+    expired: broadcast::Sender<(usize, String)>,
+    // puts and deletes with their database, see `RequestSubscribe`
+    changes: broadcast::Sender<(usize, Response)>,
+    // the last `replay_size` changes, replayed to new subscribers
+    replay: Mutex<VecDeque<(usize, Response)>>,
+    replay_size: usize,
+    max_value_size: usize,
+    // mutations are appended here before they are applied, see `with_wal`
+    wal: Option<Mutex<Wal>>,
+    // most keys kept, see `with_capacity`
+    capacity: Option<usize>,
+    lru: Mutex<Lru>,
+    // keys in all databases, counted as they are stored and removed
+    keys: AtomicUsize,
+    // secret connections must present first, see `with_auth_token`
+    auth_token: Option<String>,
+    // requests handled, and when the state was created, see `info`
+    command_counter: AtomicU64,
+    start_instant: Instant,
+    // connections served at once, see `with_max_conns`
+    max_conns: usize,
+}
+
+// a value's bytes as kept in the store, see `RequestPut.compress`
+#[derive(Debug, Clone)]
+struct Stored {
+    bytes: Vec<u8>,
+    compressed: bool,
+}
+
+impl Stored {
+    fn plain(bytes: Vec<u8>) -> Self {
+        Self {
+            bytes,
+            compressed: false,
+        }
+    }
+
+    fn compress(value: &[u8]) -> Result<Self, KvError> {
+        let bytes = zstd::encode_all(value, COMPRESSION_LEVEL)
+            .map_err(|e| KvError::Internal(format!("failed to compress: {}", e)))?;
+        Ok(Self {
+            bytes,
+            compressed: true,
+        })
+    }
+
+    fn new(value: &[u8], compress: bool) -> Result<Self, KvError> {
+        if compress {
+            Self::compress(value)
+        } else {
+            Ok(Self::plain(value.to_vec()))
+        }
+    }
+
+    // the value as it was put, fails if compressed bytes don't decompress
+    fn value(&self) -> Result<Vec<u8>, KvError> {
+        if self.compressed {
+            zstd::decode_all(&self.bytes[..])
+                .map_err(|e| KvError::Internal(format!("failed to decompress: {}", e)))
+        } else {
+            Ok(self.bytes.clone())
+        }
+    }
+
+    // like `value`, without copying plain bytes
+    fn into_value(self) -> Result<Vec<u8>, KvError> {
+        if self.compressed {
+            self.value()
+        } else {
+            Ok(self.bytes)
+        }
+    }
+}
+
+// the wal file, see `with_wal`
+#[derive(Debug)]
+struct Wal {
+    file: File,
+    // length of the whole records in `file`
+    len: u64,
+    // set once a write failed, nothing is logged after it
+    failed: bool,
+}
+
+impl Wal {
+    fn open(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let len = file.metadata()?.len();
+        Ok(Self {
+            file,
+            len,
+            failed: false,
+        })
+    }
+
+    fn check(&self) -> Result<(), KvError> {
+        if self.failed {
+            return Err(KvError::Internal(
+                "the wal failed, writes are refused".into(),
+            ));
+        }
+        Ok(())
+    }
+
+    // returns once the record is written, so a reply is only sent for a
+    // mutation the log holds
+    fn append(&mut self, record: &[u8]) -> Result<(), KvError> {
+        self.check()?;
+        if let Err(e) = self.file.write_all(record) {
+            error!("Failed to write the wal, refusing further writes: {}", e);
+            self.failed = true;
+            // a torn record would end the replay before any later one
+            if let Err(e) = self.file.set_len(self.len) {
+                error!("Failed to cut a torn record off the wal: {}", e);
+            }
+            return Err(KvError::Internal(format!("failed to write the wal: {}", e)));
+        }
+        self.len += record.len() as u64;
+        Ok(())
+    }
+
+    fn truncate(&mut self) -> Result<()> {
+        self.check()?;
+        self.file.set_len(0)?;
+        self.len = 0;
+        Ok(())
+    }
+}
+
+// recency order of the keys of all databases, only kept with a capacity
+#[derive(Debug, Default)]
+struct Lru {
+    tick: u64,
+    ticks: HashMap<(usize, String), u64>,
+    order: BTreeMap<u64, (usize, String)>,
+}
+
+impl Lru {
+    fn touch(&mut self, db: usize, key: &str) {
+        self.tick += 1;
+        if let Some(old) = self.ticks.insert((db, key.to_owned()), self.tick) {
+            self.order.remove(&old);
+        }
+        self.order.insert(self.tick, (db, key.to_owned()));
+    }
+
+    fn remove(&mut self, db: usize, key: &str) {
+        if let Some(tick) = self.ticks.remove(&(db, key.to_owned())) {
+            self.order.remove(&tick);
+        }
+    }
+
+    fn pop_oldest(&mut self) -> Option<(usize, String)> {
+        let (_, key) = self.order.pop_first()?;
+        self.ticks.remove(&key);
+        Some(key)
+    }
 }
 
 impl Default for ServerState {
@@ -23,55 +236,2376 @@ impl Default for ServerState {
 }
 
 impl ServerState {
-    pub(crate) fn new() -> Self {
+    pub fn new() -> Self {
+        Self::with_clock(Arc::new(SystemClock))
+    }
+
+    pub fn with_clock(clock: Arc<dyn Clock>) -> Self {
+        let (expired, _) = broadcast::channel(EXPIRED_CHANNEL_SIZE);
+        let (changes, _) = broadcast::channel(CHANGE_CHANNEL_SIZE);
         Self {
-            store: DashMap::new(),
+            dbs: (0..DATABASES).map(|_| DashMap::new()).collect(),
+            expired,
+            changes,
+            replay: Mutex::new(VecDeque::new()),
+            replay_size: 0,
+            max_value_size: MAX_VALUE_SIZE,
+            wal: None,
+            capacity: None,
+            lru: Mutex::default(),
+            keys: AtomicUsize::new(0),
+            auth_token: None,
+            command_counter: AtomicU64::new(0),
+            start_instant: clock.now(),
+            max_conns: MAX_CONNS,
+            clock,
+        }
+    }
+
+    /// Keeps the last `n` changes for new subscribers, 0 disables replay.
+    pub fn with_replay_size(mut self, n: usize) -> Self {
+        self.replay_size = n;
+        self
+    }
+
+    /// Rejects puts of values larger than `n` bytes.
+    pub fn with_max_value_size(mut self, n: usize) -> Self {
+        self.max_value_size = n;
+        self
+    }
+
+    /// Logs every mutation to `path` before applying it, so writes made
+    /// since the last snapshot can be recovered with `replay_wal`.
+    ///
+    /// The log isn't synced, it survives a crash of the process but not of
+    /// the machine. After a failed write the log is cut back to its last
+    /// whole record and every later mutation fails.
+    pub fn with_wal(mut self, path: impl AsRef<Path>) -> Result<Self> {
+        self.wal = Some(Mutex::new(Wal::open(path.as_ref())?));
+        Ok(self)
+    }
+
+    /// Keeps at most `max_entries` keys, writing a new key beyond that evicts
+    /// the least recently used one.
+    ///
+    /// Panics if `max_entries` is 0, every write would be evicted at once.
+    pub fn with_capacity(mut self, max_entries: usize) -> Self {
+        assert!(max_entries > 0, "capacity must be at least 1");
+        self.capacity = Some(max_entries);
+        self
+    }
+
+    /// Serves at most `n` connections at once, `serve` stops accepting until
+    /// one of them closes.
+    ///
+    /// Panics if `n` is 0, no connection would ever be served.
+    pub fn with_max_conns(mut self, n: usize) -> Self {
+        assert!(n > 0, "max_conns must be at least 1");
+        self.max_conns = n;
+        self
+    }
+
+    /// Requires every connection to open with a `RequestAuth` carrying
+    /// `token`, connections that don't are answered unauthorized and closed.
+    pub fn with_auth_token(mut self, token: impl Into<String>) -> Self {
+        self.auth_token = Some(token.into());
+        self
+    }
+
+    /// The database numbered `index`, `0..DATABASES`.
+    pub fn db(&self, index: usize) -> Result<Db<'_>, KvError> {
+        if index >= DATABASES {
+            return Err(KvError::BadRequest(format!(
+                "no database {}, there are {}",
+                index, DATABASES
+            )));
+        }
+        Ok(Db { state: self, index })
+    }
+
+    // what connections start on and the key methods below act on
+    fn default_db(&self) -> Db<'_> {
+        Db {
+            state: self,
+            index: 0,
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Result<Option<Vec<u8>>, KvError> {
+        self.default_db().get(key)
+    }
+
+    pub fn put(&self, key: String, value: Vec<u8>, ttl: Option<Duration>) -> Result<(), KvError> {
+        self.default_db().put(key, value, ttl)
+    }
+
+    pub fn put_compressed(
+        &self,
+        key: String,
+        value: Vec<u8>,
+        ttl: Option<Duration>,
+    ) -> Result<(), KvError> {
+        self.default_db().put_compressed(key, value, ttl)
+    }
+
+    pub fn del(&self, key: &str) -> Result<Option<Vec<u8>>, KvError> {
+        self.default_db().del(key)
+    }
+
+    pub fn incr(&self, key: String, delta: i64) -> Result<Option<Vec<u8>>, KvError> {
+        self.default_db().incr(key, delta)
+    }
+
+    pub fn cas(
+        &self,
+        key: String,
+        expected: &[u8],
+        new: Vec<u8>,
+    ) -> Result<Option<Vec<u8>>, KvError> {
+        self.default_db().cas(key, expected, new)
+    }
+
+    pub fn setnx(&self, key: String, value: Vec<u8>) -> Result<Option<Vec<u8>>, KvError> {
+        self.default_db().setnx(key, value)
+    }
+
+    pub fn getset(&self, key: String, value: Vec<u8>) -> Result<Option<Vec<u8>>, KvError> {
+        self.default_db().getset(key, value)
+    }
+
+    pub fn rename(&self, from: &str, to: String) -> Result<Option<Vec<u8>>, KvError> {
+        self.default_db().rename(from, to)
+    }
+
+    pub fn scan(&self, prefix: &str, limit: usize) -> Result<Vec<ScanEntry>, KvError> {
+        self.default_db().scan(prefix, limit)
+    }
+
+    pub fn range_scan(&self, prefix: &str, min: i64, max: i64, limit: usize) -> Vec<String> {
+        self.default_db().range_scan(prefix, min, max, limit)
+    }
+
+    pub fn apply(&self, req: Request) -> Response {
+        self.default_db().apply(req)
+    }
+
+    // any token is accepted when none is configured
+    fn check_token(&self, token: &str) -> bool {
+        self.auth_token
+            .as_deref()
+            .is_none_or(|t| constant_time_eq(t.as_bytes(), token.as_bytes()))
+    }
+
+    // must not be called with a shard locked, evicting takes the victim's.
+    // Evictions are logged as deletes, unless `log` is false while loading
+    // the files that would record them
+    fn evict_lru(&self, log: bool) {
+        let capacity = match self.capacity {
+            Some(capacity) => capacity,
+            None => return,
+        };
+        let mut lru = self.lru.lock().unwrap();
+        while self.keys.load(atomic::Ordering::Relaxed) > capacity {
+            let (index, victim) = match lru.pop_oldest() {
+                Some(key) => key,
+                None => break,
+            };
+            let db = Db { state: self, index };
+            // a key removed since it was last used is already gone
+            if let Entry::Occupied(entry) = db.store().entry(victim) {
+                // an eviction is a delete to the wal and to subscribers
+                if log {
+                    if let Err(e) = db.append_wal(Request::new_del(entry.key()), None) {
+                        error!("Failed to log the eviction of {}: {}", entry.key(), e);
+                    }
+                }
+                db.publish(entry.key(), None);
+                let (key, _) = entry.remove_entry();
+                self.count_keys(-1);
+                info!("Evicted key {} of database {}", key, index);
+            }
+        }
+    }
+
+    // remaining time to live in ms, 0 for none; rounded up so a live key
+    // never comes back without a ttl
+    fn ttl_ms(&self, deadline: Option<Instant>) -> u64 {
+        match deadline {
+            Some(d) => (d.saturating_duration_since(self.clock.now()).as_millis() as u64).max(1),
+            None => 0,
+        }
+    }
+
+    // the unix time in ms `deadline` falls on, 0 for none
+    fn unix_ms(&self, deadline: Option<Instant>) -> u64 {
+        let d = match deadline {
+            Some(d) => d,
+            None => return 0,
+        };
+        let left = d.saturating_duration_since(self.clock.now());
+        match self.clock.system_time().checked_add(left) {
+            Some(at) => at
+                .duration_since(UNIX_EPOCH)
+                .map_or(1, |since| {
+                    u64::try_from(since.as_millis()).unwrap_or(u64::MAX)
+                })
+                .max(1),
+            None => u64::MAX,
+        }
+    }
+
+    // the time left until unix time `ms`, `None` once it has passed
+    fn ttl_until(&self, ms: u64) -> Option<Duration> {
+        let at = UNIX_EPOCH.checked_add(Duration::from_millis(ms))?;
+        at.duration_since(self.clock.system_time())
+            .ok()
+            .filter(|left| !left.is_zero())
+    }
+
+    // when a key written now with `ttl` expires, a ttl too large for the
+    // clock is invalid
+    fn deadline(&self, ttl: Option<Duration>) -> Result<Option<Instant>, KvError> {
+        ttl.map(|ttl| {
+            self.clock.now().checked_add(ttl).ok_or_else(|| {
+                KvError::BadRequest(format!("ttl of {}ms is too large", ttl.as_millis()))
+            })
+        })
+        .transpose()
+    }
+
+    fn is_expired(&self, deadline: Option<Instant>) -> bool {
+        matches!(deadline, Some(d) if d <= self.clock.now())
+    }
+
+    /// Writes all live entries to `path`, returns the number of entries saved.
+    pub fn save_snapshot(&self, path: impl AsRef<Path>) -> Result<usize> {
+        let now = self.clock.now();
+        let mut buf = BytesMut::new();
+        buf.put_slice(SNAPSHOT_MAGIC);
+        buf.put_u16(SNAPSHOT_VERSION);
+        let mut n = 0;
+        for (db, store) in self.dbs.iter().enumerate() {
+            for entry in store.iter() {
+                let (stored, deadline) = entry.value();
+                if matches!(deadline, Some(d) if *d <= now) {
+                    continue;
+                }
+                let msg = SnapshotEntry {
+                    key: entry.key().clone(),
+                    value: stored.bytes.clone(),
+                    ttl_ms: self.ttl_ms(*deadline),
+                    compressed: stored.compressed,
+                    db: db as u32,
+                };
+                msg.encode_length_delimited(&mut buf)?;
+                n += 1;
+            }
+        }
+        buf.put_u32(crc32fast::hash(&buf));
+
+        // write next to the target and rename, a crash never leaves half a file
+        let path = path.as_ref();
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, &buf)?;
+        fs::rename(&tmp, path)?;
+        Ok(n)
+    }
+
+    /// Loads entries saved by `save_snapshot` into the store, returns the
+    /// number of entries loaded. A missing file loads nothing.
+    pub fn load_snapshot(&self, path: impl AsRef<Path>) -> Result<usize> {
+        let data = match read_if_exists(path)? {
+            Some(data) => data,
+            None => return Ok(0),
+        };
+        if data.len() < SNAPSHOT_HEADER_LEN + SNAPSHOT_CHECKSUM_LEN
+            || &data[..SNAPSHOT_MAGIC.len()] != SNAPSHOT_MAGIC
+        {
+            bail!("corrupt snapshot: bad header");
+        }
+        let (content, mut checksum) = data.split_at(data.len() - SNAPSHOT_CHECKSUM_LEN);
+        if crc32fast::hash(content) != checksum.get_u32() {
+            bail!("corrupt snapshot: checksum mismatch");
+        }
+        let mut buf = &content[SNAPSHOT_MAGIC.len()..];
+        let version = buf.get_u16();
+        if version != SNAPSHOT_VERSION {
+            bail!("unsupported snapshot version {}", version);
+        }
+
+        let mut n = 0;
+        while buf.has_remaining() {
+            let entry = SnapshotEntry::decode_length_delimited(&mut buf)?;
+            let ttl = (entry.ttl_ms > 0).then(|| Duration::from_millis(entry.ttl_ms));
+            let stored = Stored {
+                bytes: entry.value,
+                compressed: entry.compressed,
+            };
+            // checked now rather than failing every read of the key
+            if stored.compressed {
+                if let Err(e) = stored.value() {
+                    bail!("corrupt snapshot: {}", e);
+                }
+            }
+            let db = match self.db(entry.db as usize) {
+                Ok(db) => db,
+                Err(_) => bail!("corrupt snapshot: no database {}", entry.db),
+            };
+            if let Err(e) = db.insert(entry.key, stored, ttl) {
+                bail!("corrupt snapshot: {}", e);
+            }
+            n += 1;
+        }
+        Ok(n)
+    }
+
+    /// Applies the records logged by `with_wal` at `path`, returns the
+    /// number of records replayed. A missing file replays nothing, a record
+    /// cut short by a crash ends the replay.
+    ///
+    /// Records only put and delete keys, a rename is logged as both, so
+    /// replaying a log over a snapshot that already holds some of its writes
+    /// ends in the same state.
+    pub fn replay_wal(&self, path: impl AsRef<Path>) -> Result<usize> {
+        let data = match read_if_exists(path)? {
+            Some(data) => data,
+            None => return Ok(0),
+        };
+        let mut buf = &data[..];
+        let mut n = 0;
+        while buf.has_remaining() {
+            let record = match WalRecord::decode_length_delimited(&mut buf) {
+                Ok(record) => record,
+                Err(e) => {
+                    warn!("Stopping wal replay at a truncated record: {}", e);
+                    break;
+                }
+            };
+            let db = match self.db(record.db as usize) {
+                Ok(db) => db,
+                Err(_) => bail!("corrupt wal: no database {}", record.db),
+            };
+            match record.request.and_then(|request| request.command) {
+                Some(Command::Put(RequestPut {
+                    key,
+                    value,
+                    compress,
+                    ..
+                })) => {
+                    let ttl = match record.expires_at_ms {
+                        0 => None,
+                        ms => match self.ttl_until(ms) {
+                            Some(ttl) => Some(ttl),
+                            // expired while the server was down
+                            None => {
+                                db.discard(&key);
+                                n += 1;
+                                continue;
+                            }
+                        },
+                    };
+                    if let Err(e) = db.insert(key, Stored::new(&value, compress)?, ttl) {
+                        bail!("corrupt wal: {}", e);
+                    }
+                }
+                Some(Command::Del(RequestDel { key })) => db.discard(&key),
+                _ => bail!("corrupt wal: unexpected record"),
+            }
+            n += 1;
+        }
+        Ok(n)
+    }
+
+    /// Empties the wal, once a snapshot holds everything it recorded.
+    pub fn truncate_wal(&self) -> Result<()> {
+        match &self.wal {
+            Some(wal) => wal.lock().unwrap().truncate(),
+            None => Ok(()),
+        }
+    }
+
+    /// Removes every expired entry of every database and notifies expiry
+    /// subscribers, returns the number of keys removed.
+    pub fn purge_expired(&self) -> usize {
+        (0..DATABASES)
+            .map(|index| Db { state: self, index }.purge_expired())
+            .sum()
+    }
+
+    /// Metrics reported by `RequestInfo`: the number of keys, the bytes
+    /// their keys and stored values take, the uptime in seconds and the
+    /// number of requests served, all databases together.
+    pub fn info(&self) -> Vec<(&'static str, u64)> {
+        let keys = self.keys.load(atomic::Ordering::Relaxed);
+        let memory: usize = self
+            .dbs
+            .iter()
+            .flat_map(|store| store.iter())
+            .map(|e| e.key().len() + e.value().0.bytes.len())
+            .sum();
+        let uptime = self
+            .clock
+            .now()
+            .saturating_duration_since(self.start_instant);
+        vec![
+            ("keys", keys as u64),
+            ("memory_bytes", memory as u64),
+            ("uptime_secs", uptime.as_secs()),
+            (
+                "commands",
+                self.command_counter.load(atomic::Ordering::Relaxed),
+            ),
+        ]
+    }
+
+    fn count_keys(&self, delta: isize) {
+        if delta >= 0 {
+            self.keys
+                .fetch_add(delta as usize, atomic::Ordering::Relaxed);
+        } else {
+            self.keys
+                .fetch_sub(delta.unsigned_abs(), atomic::Ordering::Relaxed);
+        }
+    }
+
+    fn count_command(&self) {
+        self.command_counter.fetch_add(1, atomic::Ordering::Relaxed);
+    }
+}
+
+/// One of the server's databases, see `ServerState::db`. The key methods of
+/// `ServerState` itself act on database 0.
+#[derive(Debug, Clone, Copy)]
+pub struct Db<'a> {
+    state: &'a ServerState,
+    index: usize,
+}
+
+impl<'a> Db<'a> {
+    fn store(&self) -> &'a DashMap<String, (Stored, Option<Instant>)> {
+        &self.state.dbs[self.index]
+    }
+
+    fn touch(&self, key: &str) {
+        if self.state.capacity.is_some() {
+            self.state.lru.lock().unwrap().touch(self.index, key);
+        }
+    }
+
+    fn forget(&self, key: &str) {
+        if self.state.capacity.is_some() {
+            self.state.lru.lock().unwrap().remove(self.index, key);
+        }
+    }
+
+    // removes `key` without logging or publishing it, for loading
+    fn discard(&self, key: &str) {
+        if self.store().remove(key).is_some() {
+            self.state.count_keys(-1);
+        }
+        self.forget(key);
+    }
+
+    // `deadline` is the expiry of a put, `None` for other records
+    fn append_wal(&self, request: Request, deadline: Option<Instant>) -> Result<(), KvError> {
+        match &self.state.wal {
+            Some(wal) => wal
+                .lock()
+                .unwrap()
+                .append(&self.wal_record(request, deadline)),
+            None => Ok(()),
+        }
+    }
+
+    fn wal_record(&self, request: Request, deadline: Option<Instant>) -> Vec<u8> {
+        let record = WalRecord {
+            db: self.index as u32,
+            request: Some(request),
+            expires_at_ms: self.state.unix_ms(deadline),
+        };
+        record.encode_length_delimited_to_vec()
+    }
+
+    // incr and cas are logged as the put of their result, replaying every
+    // record is then idempotent
+    fn log_put(
+        &self,
+        key: &str,
+        value: &[u8],
+        deadline: Option<Instant>,
+        compress: bool,
+    ) -> Result<(), KvError> {
+        self.append_wal(put_request(key, value, compress), deadline)
+    }
+
+    // a put of `value` to `key`, or its delete with `None`. Called with the
+    // key's entry locked, so events are in the order writes are applied
+    fn publish(&self, key: &str, value: Option<&[u8]>) {
+        if self.state.replay_size == 0 && self.state.changes.receiver_count() == 0 {
+            return;
+        }
+        let event = match value {
+            Some(value) => Response::new(key.to_owned(), value.to_vec()),
+            None => Response::not_found(key.to_owned()),
+        };
+        // the lock orders publishing against `subscribe`, so a subscriber
+        // never misses or sees twice an event around the replay
+        let mut replay = self.state.replay.lock().unwrap();
+        if self.state.replay_size > 0 {
+            if replay.len() == self.state.replay_size {
+                replay.pop_front();
+            }
+            replay.push_back((self.index, event.clone()));
+        }
+        let _ = self.state.changes.send((self.index, event));
+    }
+
+    // the buffered changes to keys starting with `prefix` and a receiver for
+    // the ones after them, which still has to be filtered
+    fn subscribe(&self, prefix: &str) -> (Vec<Response>, broadcast::Receiver<(usize, Response)>) {
+        let replay = self.state.replay.lock().unwrap();
+        let buffered = replay
+            .iter()
+            .filter(|(db, e)| *db == self.index && e.key.starts_with(prefix))
+            .map(|(_, e)| e.clone())
+            .collect();
+        (buffered, self.state.changes.subscribe())
+    }
+
+    fn notify_expired(&self, key: String) {
+        self.forget(&key);
+        // nobody listening is fine
+        let _ = self.state.expired.send((self.index, key));
+    }
+
+    /// Returns the value if present and not expired, expired entries are
+    /// removed on access.
+    pub fn get(&self, key: &str) -> Result<Option<Vec<u8>>, KvError> {
+        match self.store().get(key) {
+            Some(entry) if !self.state.is_expired(entry.1) => {
+                // copied out, decompressing doesn't hold the shard's lock
+                let stored = entry.0.clone();
+                drop(entry);
+                self.touch(key);
+                return stored.into_value().map(Some);
+            }
+            Some(_) => {}
+            None => return Ok(None),
+        }
+        if let Some((key, _)) = self
+            .store()
+            .remove_if(key, |_, (_, d)| self.state.is_expired(*d))
+        {
+            self.state.count_keys(-1);
+            self.notify_expired(key);
+        }
+        Ok(None)
+    }
+
+    pub fn put(&self, key: String, value: Vec<u8>, ttl: Option<Duration>) -> Result<(), KvError> {
+        self.put_with(key, value, ttl, false)
+    }
+
+    /// Like `put`, but keeps the value zstd-compressed, `get` still returns
+    /// it as put.
+    pub fn put_compressed(
+        &self,
+        key: String,
+        value: Vec<u8>,
+        ttl: Option<Duration>,
+    ) -> Result<(), KvError> {
+        self.put_with(key, value, ttl, true)
+    }
+
+    fn put_with(
+        &self,
+        key: String,
+        value: Vec<u8>,
+        ttl: Option<Duration>,
+        compress: bool,
+    ) -> Result<(), KvError> {
+        let deadline = self.state.deadline(ttl)?;
+        let stored = Stored::new(&value, compress)?;
+        // logging under the entry's lock keeps the wal in the store's order
+        let entry = self.store().entry(key.clone());
+        self.log_put(&key, &value, deadline, compress)?;
+        let (entry, new) = set_entry(entry, (stored, deadline));
+        if new {
+            self.state.count_keys(1);
+        }
+        self.publish(&key, Some(&value));
+        drop(entry);
+        self.touch(&key);
+        self.state.evict_lru(true);
+        Ok(())
+    }
+
+    fn insert(&self, key: String, stored: Stored, ttl: Option<Duration>) -> Result<(), KvError> {
+        let deadline = self.state.deadline(ttl)?;
+        self.touch(&key);
+        if self.store().insert(key, (stored, deadline)).is_none() {
+            self.state.count_keys(1);
+        }
+        self.state.evict_lru(false);
+        Ok(())
+    }
+
+    pub fn del(&self, key: &str) -> Result<Option<Vec<u8>>, KvError> {
+        let entry = match self.store().entry(key.to_owned()) {
+            Entry::Occupied(e) => e,
+            Entry::Vacant(_) => return Ok(None),
+        };
+        if self.state.is_expired(entry.get().1) {
+            let (key, _) = entry.remove_entry();
+            self.state.count_keys(-1);
+            self.notify_expired(key);
+            return Ok(None);
+        }
+        self.append_wal(Request::new_del(key), None)?;
+        self.publish(key, None);
+        let (key, (stored, _)) = entry.remove_entry();
+        self.state.count_keys(-1);
+        self.forget(&key);
+        stored.into_value().map(Some)
+    }
+
+    // a read-modify-write of `key` under its entry's lock, nothing can
+    // interleave between reading and writing. `f` gets the live value, `None`
+    // if missing or expired, and returns the new one or `None` to leave the
+    // key alone; a new value keeps the key's ttl and is stored uncompressed
+    fn with_key_lock<F>(&self, key: String, f: F) -> Result<Option<Vec<u8>>, KvError>
+    where
+        F: FnOnce(Option<&[u8]>) -> Result<Option<Vec<u8>>, KvError>,
+    {
+        let entry = self.store().entry(key.clone());
+        let (current, deadline) = match &entry {
+            Entry::Occupied(e) if !self.state.is_expired(e.get().1) => {
+                let (stored, deadline) = e.get();
+                (Some(stored.value()?), *deadline)
+            }
+            _ => (None, None),
+        };
+        let value = match f(current.as_deref())? {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+        self.log_put(&key, &value, deadline, false)?;
+        let (entry, new) = set_entry(entry, (Stored::plain(value.clone()), deadline));
+        if new {
+            self.state.count_keys(1);
+        }
+        self.publish(&key, Some(&value));
+        drop(entry);
+        self.touch(&key);
+        self.state.evict_lru(true);
+        Ok(Some(value))
+    }
+
+    /// Adds `delta` to the little-endian i64 stored at `key`, a missing or
+    /// expired key counts as 0. Returns the new value's bytes.
+    pub fn incr(&self, key: String, delta: i64) -> Result<Option<Vec<u8>>, KvError> {
+        self.with_key_lock(key, |current| {
+            let current = match current {
+                Some(value) => {
+                    let bytes: [u8; 8] = value.try_into().map_err(|_| KvError::NotAnInteger)?;
+                    i64::from_le_bytes(bytes)
+                }
+                None => 0,
+            };
+            let value = current
+                .checked_add(delta)
+                .ok_or_else(|| KvError::BadRequest("increment overflows".into()))?;
+            Ok(Some(value.to_le_bytes().to_vec()))
+        })
+    }
+
+    /// Replaces the value of `key` with `new` if it currently is `expected`,
+    /// a missing key only matches an empty `expected`. Returns the new value,
+    /// `None` if the key is missing, or `Conflict` on a mismatch.
+    pub fn cas(
+        &self,
+        key: String,
+        expected: &[u8],
+        new: Vec<u8>,
+    ) -> Result<Option<Vec<u8>>, KvError> {
+        self.with_key_lock(key, |current| match current {
+            Some(value) if value != expected => Err(KvError::Conflict),
+            Some(_) => Ok(Some(new)),
+            // expired keys count as missing
+            None if expected.is_empty() => Ok(Some(new)),
+            None => Ok(None),
+        })
+    }
+
+    /// Sets `key` to `value` only if it is missing or expired, returns the
+    /// value or `AlreadyExists`.
+    pub fn setnx(&self, key: String, value: Vec<u8>) -> Result<Option<Vec<u8>>, KvError> {
+        self.with_key_lock(key, |current| match current {
+            Some(_) => Err(KvError::AlreadyExists),
+            None => Ok(Some(value)),
+        })
+    }
+
+    /// Sets `key` to `value` without a ttl, returns the value it replaced,
+    /// `None` if the key was missing or expired.
+    pub fn getset(&self, key: String, value: Vec<u8>) -> Result<Option<Vec<u8>>, KvError> {
+        let entry = self.store().entry(key.clone());
+        let old = match &entry {
+            Entry::Occupied(e) if !self.state.is_expired(e.get().1) => Some(e.get().0.clone()),
+            _ => None,
+        };
+        self.log_put(&key, &value, None, false)?;
+        let (entry, new) = set_entry(entry, (Stored::plain(value.clone()), None));
+        if new {
+            self.state.count_keys(1);
+        }
+        self.publish(&key, Some(&value));
+        drop(entry);
+        self.touch(&key);
+        self.state.evict_lru(true);
+        old.map(Stored::into_value).transpose()
+    }
+
+    /// Moves the value of `from`, with its ttl, to `to`, overwriting `to` if
+    /// it is set. Returns the value moved, `None` if `from` is missing or
+    /// expired.
+    ///
+    /// Both keys' shards are locked for the move, so nobody sees the value
+    /// under both keys or neither.
+    pub fn rename(&self, from: &str, to: String) -> Result<Option<Vec<u8>>, KvError> {
+        if from == to {
+            return self.get(from);
+        }
+        let (i, j) = (
+            self.store().determine_map(from),
+            self.store().determine_map(&to),
+        );
+        let shards = self.store().shards();
+        // locked in index order, renames between the same two shards in
+        // opposite directions can't deadlock
+        let mut low = shards[i.min(j)].write();
+        let mut high = (i != j).then(|| shards[i.max(j)].write());
+
+        let source = if i <= j {
+            &mut *low
+        } else {
+            high.as_mut().unwrap()
+        };
+        let (stored, deadline) = match source.get(from) {
+            Some(v) if !self.state.is_expired(v.get().1) => v.get().clone(),
+            _ => return Ok(None),
+        };
+        let value = stored.value()?;
+        // logged as a delete and a put in one append, replaying them over a
+        // snapshot that already holds the rename changes nothing
+        if let Some(wal) = &self.state.wal {
+            let put = put_request(&to, &value, stored.compressed);
+            let mut records = self.wal_record(Request::new_del(from), None);
+            records.extend(self.wal_record(put, deadline));
+            wal.lock().unwrap().append(&records)?;
+        }
+        source.remove(from);
+        let target = if j <= i {
+            &mut *low
+        } else {
+            high.as_mut().unwrap()
+        };
+        let replaced = target.insert(to.clone(), SharedValue::new((stored, deadline)));
+        // `from` is gone, `to` only adds a key if it was missing
+        if replaced.is_some() {
+            self.state.count_keys(-1);
+        }
+        self.publish(from, None);
+        self.publish(&to, Some(&value));
+        drop(high);
+        drop(low);
+
+        // the lru is locked before shards, see `evict_lru`
+        self.forget(from);
+        self.touch(&to);
+        Ok(Some(value))
+    }
+
+    /// Removes every expired entry and notifies expiry subscribers, returns
+    /// the number of keys removed.
+    fn purge_expired(&self) -> usize {
+        let mut expired = Vec::new();
+        self.store().retain(|k, (_, d)| {
+            if self.state.is_expired(*d) {
+                expired.push(k.clone());
+                false
+            } else {
+                true
+            }
+        });
+        let n = expired.len();
+        self.state.count_keys(-(n as isize));
+        for key in expired {
+            self.notify_expired(key);
+        }
+        n
+    }
+
+    /// Returns up to `limit` live entries whose key starts with `prefix`, in
+    /// ascending key order.
+    ///
+    /// Each shard is collected and sorted on its own, keeping at most `limit`
+    /// entries per shard, then the sorted runs are k-way merged.
+    pub fn scan(&self, prefix: &str, limit: usize) -> Result<Vec<ScanEntry>, KvError> {
+        let mut runs: Vec<_> = self
+            .store()
+            .shards()
+            .iter()
+            .map(|shard| {
+                let shard = shard.read();
+                // copied out, values are decompressed once the lock is released
+                let mut run: Vec<_> = shard
+                    .iter()
+                    .filter(|(k, v)| k.starts_with(prefix) && !self.state.is_expired(v.get().1))
+                    .map(|(k, v)| (k.clone(), v.get().0.clone()))
+                    .collect();
+                run.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+                run.truncate(limit);
+                run.into_iter().peekable()
+            })
+            .collect();
+
+        let mut heap = BinaryHeap::new();
+        for (i, run) in runs.iter_mut().enumerate() {
+            if let Some((key, _)) = run.peek() {
+                heap.push(Reverse((key.clone(), i)));
+            }
+        }
+        let mut result = Vec::new();
+        while result.len() < limit {
+            let Some(Reverse((_, i))) = heap.pop() else {
+                break;
+            };
+            let (key, stored) = runs[i].next().expect("a run in the heap has a head");
+            if let Some((next, _)) = runs[i].peek() {
+                heap.push(Reverse((next.clone(), i)));
+            }
+            let value = stored.into_value()?;
+            result.push(ScanEntry { key, value });
+        }
+        Ok(result)
+    }
+
+    /// Returns up to `limit` live keys starting with `prefix` whose trailing
+    /// integer, e.g. the `1700000000` of `ts:1700000000`, is within
+    /// `min..=max`, in ascending order of that integer. Keys without one are
+    /// skipped, a minus sign only counts right after the prefix.
+    pub fn range_scan(&self, prefix: &str, min: i64, max: i64, limit: usize) -> Vec<String> {
+        let mut keys: Vec<_> = self
+            .store()
+            .iter()
+            .filter(|e| e.key().starts_with(prefix) && !self.state.is_expired(e.value().1))
+            .filter_map(|e| {
+                let n = trailing_int(&e.key()[prefix.len()..])?;
+                (min..=max).contains(&n).then(|| (n, e.key().clone()))
+            })
+            .collect();
+        keys.sort_unstable();
+        keys.truncate(limit);
+        keys.into_iter().map(|(_, key)| key).collect()
+    }
+
+    /// Runs one request against the database and returns its response,
+    /// tagged with the request's id. This is everything a connection does
+    /// except for authentication, selecting a database and subscriptions,
+    /// which only make sense over one and are answered not implemented here.
+    pub fn apply(&self, req: Request) -> Response {
+        self.state.count_command();
+        let mut response = match req.command {
+            Some(Command::Auth(RequestAuth { token })) if !self.state.check_token(&token) => {
+                Response::from_result(String::new(), Err(KvError::Unauthorized))
+            }
+            Some(Command::Auth(_)) => Response::default(),
+            Some(Command::Info(_)) => {
+                let info: String = self
+                    .state
+                    .info()
+                    .into_iter()
+                    .map(|(name, value)| format!("{}:{}\n", name, value))
+                    .collect();
+                Response::new(String::new(), info.into_bytes())
+            }
+            Some(Command::Get(RequestGet { key })) => {
+                let result = self.get(&key);
+                Response::from_result(key, result)
+            }
+            Some(Command::Put(RequestPut { key, value, .. }))
+                if value.len() > self.state.max_value_size =>
+            {
+                Response::from_result(key, Err(KvError::TooLarge))
+            }
+            Some(Command::Put(RequestPut {
+                key,
+                value,
+                ttl_ms,
+                compress,
+            })) => {
+                let ttl = (ttl_ms > 0).then(|| Duration::from_millis(ttl_ms));
+                let result = self.put_with(key.clone(), value.clone(), ttl, compress);
+                Response::from_result(key, result.map(|_| Some(value)))
+            }
+            Some(Command::Del(RequestDel { key })) => {
+                let result = self.del(&key);
+                Response::from_result(key, result)
+            }
+            Some(Command::Setnx(RequestSetNx { key, value }))
+                if value.len() > self.state.max_value_size =>
+            {
+                Response::from_result(key, Err(KvError::TooLarge))
+            }
+            Some(Command::Setnx(RequestSetNx { key, value })) => {
+                let result = self.setnx(key.clone(), value);
+                Response::from_result(key, result)
+            }
+            Some(Command::Getset(RequestGetSet { key, value }))
+                if value.len() > self.state.max_value_size =>
+            {
+                Response::from_result(key, Err(KvError::TooLarge))
+            }
+            Some(Command::Getset(RequestGetSet { key, value })) => {
+                let result = self.getset(key.clone(), value);
+                Response::from_result(key, result)
+            }
+            Some(Command::Rename(RequestRename { from, to })) => {
+                let result = self.rename(&from, to);
+                Response::from_result(from, result)
+            }
+            Some(Command::Cas(RequestCas { key, new, .. }))
+                if new.len() > self.state.max_value_size =>
+            {
+                Response::from_result(key, Err(KvError::TooLarge))
+            }
+            Some(Command::Cas(RequestCas { key, expected, new })) => {
+                let result = self.cas(key.clone(), &expected, new);
+                Response::from_result(key, result)
+            }
+            Some(Command::Incr(RequestIncr { key, delta })) => {
+                let result = self.incr(key.clone(), delta);
+                Response::from_result(key, result)
+            }
+            Some(Command::Scan(RequestScan { prefix, limit })) => {
+                let limit = if limit == 0 {
+                    usize::MAX
+                } else {
+                    limit as usize
+                };
+                match self.scan(&prefix, limit) {
+                    Ok(entries) => Response::with_entries(
+                        entries
+                            .into_iter()
+                            .map(|e| Response::new(e.key, e.value))
+                            .collect(),
+                    ),
+                    Err(e) => Response::from_result(prefix, Err(e)),
+                }
+            }
+            Some(Command::RangeScan(RequestRangeScan {
+                prefix,
+                min,
+                max,
+                limit,
+            })) => {
+                let limit = if limit == 0 {
+                    usize::MAX
+                } else {
+                    limit as usize
+                };
+                let entries = self
+                    .range_scan(&prefix, min, max, limit)
+                    .into_iter()
+                    .map(|key| Response::new(key, vec![]))
+                    .collect();
+                Response::with_entries(entries)
+            }
+            Some(Command::Mget(RequestMget { keys })) => {
+                let entries = keys
+                    .into_iter()
+                    .map(|key| {
+                        let result = self.get(&key);
+                        Response::from_result(key, result)
+                    })
+                    .collect();
+                Response::with_entries(entries)
+            }
+            // connection state, see `handle_conn`
+            Some(Command::Select(_))
+            | Some(Command::ExpiredSubscribe(_))
+            | Some(Command::Subscribe(_))
+            | Some(Command::Watch(_))
+            | None => Response::from_result(String::new(), Err(KvError::NotImplemented)),
+        };
+        response.id = req.id;
+        response
+    }
+}
+
+// a put as logged to the wal, the ttl goes in `WalRecord.expires_at_ms`
+fn put_request(key: &str, value: &[u8], compress: bool) -> Request {
+    Request {
+        command: Some(Command::Put(RequestPut {
+            key: key.to_owned(),
+            value: value.to_vec(),
+            ttl_ms: 0,
+            compress,
+        })),
+        ..Default::default()
+    }
+}
+
+// the integer the part of a key after its prefix ends with, negative only
+// when that part is just a minus sign and the digits, so the part `user-42`
+// is 42
+fn trailing_int(rest: &str) -> Option<i64> {
+    let digits = rest.len() - rest.trim_end_matches(|c: char| c.is_ascii_digit()).len();
+    if digits == 0 {
+        return None;
+    }
+    let start = rest.len() - digits;
+    if &rest[..start] == "-" {
+        return rest.parse().ok();
+    }
+    rest[start..].parse().ok()
+}
+
+/// A scanned key/value pair, ordered by key only.
+#[derive(Debug, Clone)]
+pub struct ScanEntry {
+    pub key: String,
+    pub value: Vec<u8>,
+}
+
+impl PartialEq for ScanEntry {
+    fn eq(&self, other: &Self) -> bool {
+        self.key == other.key
+    }
+}
+
+impl Eq for ScanEntry {}
+
+impl PartialOrd for ScanEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for ScanEntry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key.cmp(&other.key)
+    }
+}
+
+// compares every byte whatever the earlier ones were, so the time taken
+// doesn't tell how much of a guessed token is right
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    let diff = a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y));
+    std::hint::black_box(diff) == 0
+}
+
+// returns the entry, still locked, and whether the key is new
+fn set_entry<K: Eq + Hash, V>(entry: Entry<'_, K, V>, value: V) -> (RefMut<'_, K, V>, bool) {
+    match entry {
+        Entry::Occupied(mut e) => {
+            e.insert(value);
+            (e.into_ref(), false)
         }
+        Entry::Vacant(e) => (e.insert(value), true),
     }
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    tracing_subscriber::fmt().init();
+fn read_if_exists(path: impl AsRef<Path>) -> io::Result<Option<Vec<u8>>> {
+    match fs::read(path) {
+        Ok(data) => Ok(Some(data)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Purges expired keys every `period`, so keys never read again don't stay
+/// in memory, until `shutdown` is cancelled. `period` must not be zero.
+pub fn spawn_sweeper(
+    state: Arc<ServerState>,
+    period: Duration,
+    shutdown: CancellationToken,
+) -> JoinHandle<()> {
+    assert!(!period.is_zero(), "the sweep period must not be zero");
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            tokio::select! {
+                _ = shutdown.cancelled() => return,
+                _ = interval.tick() => {
+                    let n = state.purge_expired();
+                    if n > 0 {
+                        info!("Swept {} expired keys", n);
+                    }
+                }
+            }
+        }
+    })
+}
 
-    let state = Arc::new(ServerState::new());
-    let addr = "0.0.0.0:8888";
-    let listener = TcpListener::bind(addr).await?;
+// counts open connections, `drained` resolves once there are none left
+#[derive(Default)]
+struct Connections {
+    open: AtomicUsize,
+    closed: Notify,
+}
+
+struct ConnGuard(Arc<Connections>);
+
+impl Connections {
+    fn track(self: &Arc<Self>) -> ConnGuard {
+        self.open.fetch_add(1, atomic::Ordering::SeqCst);
+        ConnGuard(self.clone())
+    }
+
+    fn open(&self) -> usize {
+        self.open.load(atomic::Ordering::SeqCst)
+    }
+
+    async fn drained(&self) {
+        while self.open() > 0 {
+            self.closed.notified().await;
+        }
+    }
+}
 
-    info!("Listening on {}", addr);
+impl Drop for ConnGuard {
+    fn drop(&mut self) {
+        if self.0.open.fetch_sub(1, atomic::Ordering::SeqCst) == 1 {
+            // notify_one keeps a permit if `drained` isn't waiting yet
+            self.0.closed.notify_one();
+        }
+    }
+}
 
+/// Accepts connections on `listener` until `shutdown` is cancelled, serving
+/// each one on its own task, then gives open connections up to
+/// `DRAIN_TIMEOUT` to finish. At most `max_conns` are served at once, further
+/// clients wait in the listen backlog.
+pub async fn serve(
+    listener: TcpListener,
+    state: Arc<ServerState>,
+    frame: FrameConfig,
+    nodelay: bool,
+    shutdown: CancellationToken,
+) -> Result<()> {
+    let conns = Arc::new(Connections::default());
+    let limit = Arc::new(Semaphore::new(state.max_conns));
     loop {
-        let (stream, addr) = listener.accept().await?;
+        // held by the connection's task, released when it ends
+        let permit = tokio::select! {
+            _ = shutdown.cancelled() => break,
+            permit = limit.clone().acquire_owned() => permit?,
+        };
+        let (stream, addr) = tokio::select! {
+            _ = shutdown.cancelled() => break,
+            accepted = listener.accept() => accepted?,
+        };
         info!("New client {:?} accepted", addr);
+        // one bad socket, say a peer that already reset, isn't the server's
+        if let Err(e) = net::configure(&stream, nodelay) {
+            warn!("Failed to configure the connection with {:?}: {}", addr, e);
+            continue;
+        }
 
         let shared = state.clone();
+        let guard = conns.track();
 
         tokio::spawn(async move {
-            let mut stream = LengthDelimitedCodec::builder()
-                .length_field_length(2)
-                .new_framed(stream);
-
-            while let Some(Ok(buf)) = stream.next().await {
-                let msg: Request = buf.try_into()?;
-                info!("Got a command: {:?}", msg);
-                let response = match msg.command {
-                    Some(Command::Get(RequestGet { key })) => match shared.store.get(&key) {
-                        Some(v) => Response::new(key, v.value().to_vec()),
-                        None => Response::not_found(key),
-                    },
-                    Some(Command::Put(RequestPut { key, value })) => {
-                        shared.store.insert(key.clone(), value.clone());
-                        Response::new(key, value)
+            let _guard = guard;
+            let _permit = permit;
+            if let Err(e) = handle_conn(stream, shared, frame).await {
+                error!("Connection with {:?} failed: {}", addr, e);
+            }
+        });
+    }
+
+    drop(listener);
+    info!("shutting down, {} connections draining", conns.open());
+    if timeout(DRAIN_TIMEOUT, conns.drained()).await.is_err() {
+        warn!("{} connections still open, giving up", conns.open());
+    }
+    Ok(())
+}
+
+/// Serves one client until it disconnects.
+pub async fn handle_conn<S>(
+    stream: S,
+    shared: Arc<ServerState>,
+    frame: FrameConfig,
+) -> Result<(), KvError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut stream = frame.framed(stream);
+    // nothing is served before a successful auth when a token is configured
+    let mut authed = shared.auth_token.is_none();
+    let mut db = shared.default_db();
+
+    while let Some(buf) = stream.next().await {
+        let msg = match Request::try_from(buf?) {
+            Ok(msg) => msg,
+            Err(e) => {
+                // answer anyway so replies stay matched to requests
+                warn!("Failed to decode request: {}", e);
+                let response = Response::from_result(String::new(), Err(e));
+                stream.send(response.into()).await?;
+                continue;
+            }
+        };
+        info!("Got a command: {:?}", Redacted(&msg));
+        let id = msg.id;
+        if let Some(Command::Auth(RequestAuth { token })) = &msg.command {
+            authed = shared.check_token(token);
+        }
+        if !authed {
+            warn!("Closing an unauthenticated connection");
+            shared.count_command();
+            let mut response = Response::from_result(String::new(), Err(KvError::Unauthorized));
+            response.id = id;
+            stream.send(response.into()).await?;
+            return Ok(());
+        }
+        let ack = Response {
+            id,
+            ..Default::default()
+        };
+        match msg.command {
+            Some(Command::Select(RequestSelect { db: index })) => {
+                shared.count_command();
+                let mut response = match shared.db(index as usize) {
+                    Ok(selected) => {
+                        db = selected;
+                        Response::default()
                     }
-                    Some(Command::Del(RequestDel { key })) => match shared.store.remove(&key) {
-                        Some((k, v)) => Response::new(k, v),
-                        None => Response::not_found(key),
-                    },
-                    None => Response::not_impl(),
+                    Err(e) => Response::from_result(String::new(), Err(e)),
                 };
+                response.id = id;
                 stream.send(response.into()).await?;
             }
-            Ok::<(), anyhow::Error>(())
+            Some(Command::ExpiredSubscribe(_)) => {
+                shared.count_command();
+                // acknowledge, then the connection only streams expired keys
+                let mut expired = shared.expired.subscribe();
+                stream.send(ack.into()).await?;
+                loop {
+                    let received = tokio::select! {
+                        received = expired.recv() => received,
+                        // anything but the client going away is ignored
+                        incoming = stream.next() => match incoming {
+                            Some(buf) => {
+                                buf?;
+                                continue;
+                            }
+                            None => return Ok(()),
+                        },
+                    };
+                    match received {
+                        Ok((index, key)) if index == db.index => {
+                            stream.send(Response::new(key, vec![]).into()).await?
+                        }
+                        Ok(_) => {}
+                        Err(broadcast::error::RecvError::Lagged(n)) => {
+                            warn!("Expiry subscriber lagged, {} keys dropped", n)
+                        }
+                        Err(broadcast::error::RecvError::Closed) => return Ok(()),
+                    }
+                }
+            }
+            Some(Command::Subscribe(RequestSubscribe { prefix })) => {
+                shared.count_command();
+                let (buffered, changes) = db.subscribe(&prefix);
+                stream.send(ack.into()).await?;
+                for event in buffered {
+                    stream.send(event.into()).await?;
+                }
+                return stream_changes(&mut stream, db.index, &prefix, changes).await;
+            }
+            Some(Command::Watch(RequestWatch { prefix })) => {
+                shared.count_command();
+                let changes = shared.changes.subscribe();
+                stream.send(ack.into()).await?;
+                return stream_changes(&mut stream, db.index, &prefix, changes).await;
+            }
+            _ => stream.send(db.apply(msg).into()).await?,
+        }
+    }
+    Ok(())
+}
+
+// sends the changes of database `index` to keys starting with `prefix` until
+// the client goes away, a lagging client misses changes but stays connected
+async fn stream_changes<S>(
+    stream: &mut Framed<S, LengthDelimitedCodec>,
+    index: usize,
+    prefix: &str,
+    mut changes: broadcast::Receiver<(usize, Response)>,
+) -> Result<(), KvError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    loop {
+        let received = tokio::select! {
+            received = changes.recv() => received,
+            // anything but the client going away is ignored
+            incoming = stream.next() => match incoming {
+                Some(buf) => {
+                    buf?;
+                    continue;
+                }
+                None => return Ok(()),
+            },
+        };
+        match received {
+            Ok((i, event)) if i == index && event.key.starts_with(prefix) => {
+                stream.send(event.into()).await?
+            }
+            Ok(_) => {}
+            Err(broadcast::error::RecvError::Lagged(n)) => {
+                warn!("Subscriber lagged, {} changes dropped", n)
+            }
+            Err(broadcast::error::RecvError::Closed) => return Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+
+    #[test]
+    fn ttl_expires_with_mock_clock() {
+        let clock = Arc::new(MockClock::new());
+        let state = ServerState::with_clock(clock.clone());
+        state
+            .put(
+                "hello".into(),
+                b"world".to_vec(),
+                Some(Duration::from_secs(60)),
+            )
+            .unwrap();
+        state
+            .put("forever".into(), b"value".to_vec(), None)
+            .unwrap();
+        assert_eq!(state.get("hello").unwrap(), Some(b"world".to_vec()));
+
+        clock.advance(Duration::from_secs(61));
+        assert_eq!(state.get("hello").unwrap(), None);
+        assert!(!state.dbs[0].contains_key("hello"));
+        assert_eq!(state.get("forever").unwrap(), Some(b"value".to_vec()));
+    }
+
+    #[test]
+    fn concurrent_increments_are_atomic() {
+        let state = Arc::new(ServerState::new());
+        let threads: Vec<_> = (0..100)
+            .map(|_| {
+                let state = state.clone();
+                std::thread::spawn(move || state.incr("counter".into(), 1).unwrap())
+            })
+            .collect();
+        for t in threads {
+            t.join().unwrap();
+        }
+        assert_eq!(
+            state.get("counter").unwrap(),
+            Some(100i64.to_le_bytes().to_vec())
+        );
+
+        let value = state.incr("counter".into(), -142).unwrap().unwrap();
+        assert_eq!(i64::from_le_bytes(value.try_into().unwrap()), -42);
+    }
+
+    #[test]
+    fn least_recently_used_key_is_evicted() {
+        let state = ServerState::new().with_capacity(3);
+        state.put("a".into(), b"1".to_vec(), None).unwrap();
+        state.put("b".into(), b"2".to_vec(), None).unwrap();
+        state.put("c".into(), b"3".to_vec(), None).unwrap();
+        assert_eq!(state.get("a").unwrap(), Some(b"1".to_vec()));
+        // overwriting a key doesn't grow the store
+        state.put("c".into(), b"4".to_vec(), None).unwrap();
+        assert_eq!(state.dbs[0].len(), 3);
+
+        state.put("d".into(), b"5".to_vec(), None).unwrap();
+        assert_eq!(state.dbs[0].len(), 3);
+        assert_eq!(state.get("b").unwrap(), None);
+        assert_eq!(state.get("a").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(state.get("c").unwrap(), Some(b"4".to_vec()));
+        assert_eq!(state.get("d").unwrap(), Some(b"5".to_vec()));
+    }
+
+    #[test]
+    #[should_panic(expected = "capacity must be at least 1")]
+    fn zero_capacity_is_rejected() {
+        ServerState::new().with_capacity(0);
+    }
+
+    #[test]
+    fn incr_rejects_non_integers() {
+        let state = ServerState::new();
+        state.put("name".into(), b"lxb".to_vec(), None).unwrap();
+        assert_eq!(state.incr("name".into(), 1), Err(KvError::NotAnInteger));
+        assert_eq!(state.get("name").unwrap(), Some(b"lxb".to_vec()));
+
+        state
+            .put("max".into(), i64::MAX.to_le_bytes().to_vec(), None)
+            .unwrap();
+        let res = Response::from_result("max".into(), state.incr("max".into(), 1));
+        assert_eq!(res.code, 400);
+        let res = Response::from_result("name".into(), state.incr("name".into(), 1));
+        assert_eq!(res.code, 422);
+    }
+
+    #[test]
+    fn cas_only_writes_expected_values() {
+        let state = ServerState::new();
+        assert_eq!(
+            state.cas("lock".into(), b"", b"a".to_vec()),
+            Ok(Some(b"a".to_vec()))
+        );
+        assert_eq!(
+            state.cas("lock".into(), b"", b"b".to_vec()),
+            Err(KvError::Conflict)
+        );
+        assert_eq!(
+            state.cas("lock".into(), b"x", b"b".to_vec()),
+            Err(KvError::Conflict)
+        );
+        assert_eq!(
+            state.cas("lock".into(), b"a", b"b".to_vec()),
+            Ok(Some(b"b".to_vec()))
+        );
+        assert_eq!(state.get("lock").unwrap(), Some(b"b".to_vec()));
+
+        // a missing key only matches an empty expected value
+        assert_eq!(state.cas("none".into(), b"a", b"b".to_vec()), Ok(None));
+        assert_eq!(state.get("none").unwrap(), None);
+        let res = Response::from_result("none".into(), state.cas("none".into(), b"a", vec![]));
+        assert_eq!(res.code, 404);
+        let res = Response::from_result("lock".into(), state.cas("lock".into(), b"a", vec![]));
+        assert_eq!(res.code, 409);
+    }
+
+    #[test]
+    fn concurrent_incr_and_cas_dont_lose_updates() {
+        let state = ServerState::new();
+        std::thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..500 {
+                        state.incr("n".into(), 1).unwrap();
+                    }
+                });
+                // increments by cas, retrying on conflicts
+                s.spawn(|| {
+                    for _ in 0..500 {
+                        loop {
+                            let current = state.get("n").unwrap().unwrap_or_default();
+                            let n = match current.len() {
+                                0 => 0,
+                                _ => i64::from_le_bytes(current[..].try_into().unwrap()),
+                            };
+                            match state.cas("n".into(), &current, (n + 1).to_le_bytes().to_vec()) {
+                                Ok(Some(_)) => break,
+                                Err(KvError::Conflict) => continue,
+                                other => panic!("unexpected cas result {:?}", other),
+                            }
+                        }
+                    }
+                });
+            }
         });
+        assert_eq!(
+            state.get("n").unwrap(),
+            Some(4000i64.to_le_bytes().to_vec())
+        );
+    }
+
+    #[tokio::test]
+    async fn malformed_frames_dont_kill_the_connection() {
+        let state = Arc::new(ServerState::new());
+        let (client, server) = tokio::io::duplex(1024);
+        let conn = tokio::spawn(handle_conn(server, state, FrameConfig::default()));
+        let mut client = FrameConfig::default().framed(client);
+
+        client
+            .send(bytes::Bytes::from_static(b"\xff\xff\xff"))
+            .await
+            .unwrap();
+        client.send(Request::new_get("hello").into()).await.unwrap();
+        let mut replies = client.map(|buf| Response::try_from(buf.unwrap()).unwrap());
+        let res = replies.next().await.unwrap();
+        assert_eq!(res.status(), Ok(ResponseCode::BadRequest));
+        let res = replies.next().await.unwrap();
+        assert_eq!(res.status(), Ok(ResponseCode::NotFound));
+
+        drop(replies);
+        conn.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn responses_echo_request_ids() {
+        let state = Arc::new(ServerState::new());
+        let (client, server) = tokio::io::duplex(1024);
+        tokio::spawn(handle_conn(server, state, FrameConfig::default()));
+        let mut client = FrameConfig::default().framed(client);
+
+        let put = Request::new_put("a", b"1".to_vec()).with_id(7);
+        client.send(put.into()).await.unwrap();
+        let put = Request::new_put("b", b"2".to_vec()).with_id(42);
+        client.send(put.into()).await.unwrap();
+        let mut replies = client.map(|buf| Response::try_from(buf.unwrap()).unwrap());
+        let res = replies.next().await.unwrap();
+        assert_eq!((res.id, res.key.as_str()), (7, "a"));
+        let res = replies.next().await.unwrap();
+        assert_eq!((res.id, res.key.as_str()), (42, "b"));
+    }
+
+    #[tokio::test]
+    async fn authenticated_clients_are_served() {
+        let state = Arc::new(ServerState::new().with_auth_token("secret"));
+        let (client, server) = tokio::io::duplex(1024);
+        tokio::spawn(handle_conn(server, state, FrameConfig::default()));
+        let client = crate::client::KvClient::new(client, 4);
+
+        client.auth("secret").await.unwrap();
+        client.put("hello", "world").await.unwrap();
+        assert_eq!(client.get("hello").await.unwrap(), Some(b"world".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn unauthenticated_commands_are_rejected() {
+        let state = Arc::new(ServerState::new().with_auth_token("secret"));
+        for first in [Request::new_get("hello"), Request::new_auth("wrong")] {
+            let (client, server) = tokio::io::duplex(1024);
+            let conn = tokio::spawn(handle_conn(server, state.clone(), FrameConfig::default()));
+            let mut client = FrameConfig::default().framed(client);
+
+            client.send(first.into()).await.unwrap();
+            let res = Response::try_from(client.next().await.unwrap().unwrap()).unwrap();
+            assert_eq!(res.status(), Ok(ResponseCode::Unauthorized));
+            // the server hangs up
+            conn.await.unwrap().unwrap();
+            assert!(client.next().await.is_none());
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "max_conns must be at least 1")]
+    fn zero_max_conns_is_rejected() {
+        ServerState::new().with_max_conns(0);
+    }
+
+    #[test]
+    fn tokens_are_compared_whole() {
+        let state = ServerState::new().with_auth_token("secret");
+        assert!(state.check_token("secret"));
+        for wrong in ["", "secreT", "secret!", "Secret", "secre"] {
+            assert!(!state.check_token(wrong), "{:?}", wrong);
+        }
+        assert!(ServerState::new().check_token("anything"));
+    }
+
+    #[tokio::test]
+    async fn large_values_roundtrip() {
+        let state = Arc::new(ServerState::new().with_max_value_size(200 * 1024));
+        let (client, server) = tokio::io::duplex(4096);
+        tokio::spawn(handle_conn(server, state, FrameConfig::default()));
+        // the client reads replies while writing, values exceed the buffer
+        let client = crate::client::KvClient::new(client, 4);
+
+        let big: Vec<u8> = (0..100 * 1024).map(|i| i as u8).collect();
+        let huge = vec![0u8; 300 * 1024];
+        client.put("big", big.clone()).await.unwrap();
+        assert_eq!(client.get("big").await.unwrap(), Some(big));
+        let res = client.call(Request::new_put("huge", huge)).await.unwrap();
+        assert_eq!(res.status(), Ok(ResponseCode::TooLarge));
+        assert_eq!(client.get("huge").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn compressed_values_roundtrip() {
+        let state = Arc::new(ServerState::new());
+        let (client, server) = tokio::io::duplex(4096);
+        tokio::spawn(handle_conn(server, state.clone(), FrameConfig::default()));
+        let client = crate::client::KvClient::new(client, 4);
+
+        let text: Vec<u8> = b"all work and no play "
+            .iter()
+            .copied()
+            .cycle()
+            .take(1 << 20)
+            .collect();
+        let res = client
+            .call(Request::new_put_compressed("text", text.clone()))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), Ok(ResponseCode::Ok));
+        let stored = state.dbs[0].get("text").unwrap().0.clone();
+        assert!(stored.compressed);
+        assert!(stored.bytes.len() < text.len() / 100);
+        assert_eq!(client.get("text").await.unwrap(), Some(text));
+    }
+
+    #[tokio::test]
+    async fn info_reports_metrics() {
+        let clock = Arc::new(MockClock::new());
+        let state = Arc::new(ServerState::with_clock(clock.clone()));
+        let (client, server) = tokio::io::duplex(4096);
+        tokio::spawn(handle_conn(server, state, FrameConfig::default()));
+        let client = crate::client::KvClient::new(client, 4);
+
+        client.put("a", "1").await.unwrap();
+        client.put("bb", "22").await.unwrap();
+        client.put("a", "333").await.unwrap();
+        clock.advance(Duration::from_secs(90));
+        let info = client.info().await.unwrap();
+        assert_eq!(info["keys"], 2);
+        assert_eq!(info["memory_bytes"], 1 + 3 + 2 + 2);
+        assert_eq!(info["uptime_secs"], 90);
+        assert_eq!(info["commands"], 4);
+    }
+
+    #[test]
+    fn key_count_follows_every_write() {
+        let clock = Arc::new(MockClock::new());
+        let state = ServerState::with_clock(clock.clone());
+        let keys = |state: &ServerState| state.keys.load(atomic::Ordering::Relaxed);
+        state.put("a".into(), b"1".to_vec(), None).unwrap();
+        state.put("a".into(), b"2".to_vec(), None).unwrap();
+        state.incr("b".into(), 1).unwrap();
+        assert_eq!(keys(&state), 2);
+        state.rename("a", "b".into()).unwrap();
+        state.rename("b", "c".into()).unwrap();
+        assert_eq!(keys(&state), 1);
+        let ttl = Some(Duration::from_secs(1));
+        state.put("d".into(), vec![], ttl).unwrap();
+        state.put("e".into(), vec![], ttl).unwrap();
+        clock.advance(Duration::from_secs(2));
+        assert_eq!(state.get("d").unwrap(), None);
+        assert_eq!(state.purge_expired(), 1);
+        assert_eq!(keys(&state), 1);
+        state.del("c").unwrap();
+        assert_eq!(keys(&state), 0);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn setnx_has_a_single_winner() {
+        let state = Arc::new(ServerState::new());
+        let tasks: Vec<_> = (0..20)
+            .map(|i| {
+                let (client, server) = tokio::io::duplex(1024);
+                tokio::spawn(handle_conn(server, state.clone(), FrameConfig::default()));
+                let client = crate::client::KvClient::new(client, 4);
+                tokio::spawn(async move { client.setnx("lock", format!("owner{}", i)).await })
+            })
+            .collect();
+        let mut winners = Vec::new();
+        for (i, task) in tasks.into_iter().enumerate() {
+            if task.await.unwrap().unwrap() {
+                winners.push(i);
+            }
+        }
+        assert_eq!(winners.len(), 1);
+        let owner = format!("owner{}", winners[0]).into_bytes();
+        assert_eq!(state.get("lock").unwrap(), Some(owner));
+    }
+
+    #[test]
+    fn requests_are_applied_without_a_connection() {
+        let state = ServerState::new();
+        let res = state.apply(Request::new_put("hello", "world").with_id(1));
+        assert_eq!((res.code, res.id), (0, 1));
+        let res = state.apply(Request::new_get("hello").with_id(2));
+        assert_eq!(res.value, b"world");
+        assert_eq!(res.id, 2);
+
+        let res = state.apply(Request::new_del("hello"));
+        assert_eq!(res.status(), Ok(ResponseCode::Ok));
+        let res = state.apply(Request::new_get("hello"));
+        assert_eq!(res.status(), Ok(ResponseCode::NotFound));
+
+        let res = state.apply(Request::default());
+        assert_eq!(res.status(), Ok(ResponseCode::NotImplemented));
+        // subscriptions need a connection to stream on
+        let res = state.apply(Request::new_subscribe(""));
+        assert_eq!(res.status(), Ok(ResponseCode::NotImplemented));
+        assert_eq!(state.info()[3], ("commands", 6));
+    }
+
+    #[tokio::test]
+    async fn databases_are_isolated() {
+        let state = Arc::new(ServerState::new());
+        let connect = || {
+            let (client, server) = tokio::io::duplex(1024);
+            tokio::spawn(handle_conn(server, state.clone(), FrameConfig::default()));
+            crate::client::KvClient::new(client, 4)
+        };
+        let first = connect();
+        let second = connect();
+
+        let res = second.call(Request::new_select(1)).await.unwrap();
+        assert_eq!(res.status(), Ok(ResponseCode::Ok));
+        first.put("k", "zero").await.unwrap();
+        second.put("k", "one").await.unwrap();
+        assert_eq!(first.get("k").await.unwrap(), Some(b"zero".to_vec()));
+        assert_eq!(second.get("k").await.unwrap(), Some(b"one".to_vec()));
+        assert_eq!(state.get("k").unwrap(), Some(b"zero".to_vec()));
+        assert_eq!(
+            state.db(1).unwrap().get("k").unwrap(),
+            Some(b"one".to_vec())
+        );
+
+        let res = second
+            .call(Request::new_select(DATABASES as u32))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), Ok(ResponseCode::BadRequest));
+        // a failed select keeps the database
+        assert_eq!(second.get("k").await.unwrap(), Some(b"one".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn getset_returns_the_replaced_value() {
+        let state = Arc::new(ServerState::new());
+        let (client, server) = tokio::io::duplex(1024);
+        tokio::spawn(handle_conn(server, state, FrameConfig::default()));
+        let client = crate::client::KvClient::new(client, 4);
+
+        let res = client.call(Request::new_getset("k", "v1")).await.unwrap();
+        assert_eq!(res.status(), Ok(ResponseCode::NotFound));
+        assert!(res.value.is_empty());
+        client.put("k", "v2").await.unwrap();
+        let res = client.call(Request::new_getset("k", "v3")).await.unwrap();
+        assert_eq!(res.status(), Ok(ResponseCode::Ok));
+        assert_eq!(res.value, b"v2");
+        assert_eq!(client.get("k").await.unwrap(), Some(b"v3".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn rename_moves_the_value() {
+        let state = Arc::new(ServerState::new());
+        let (client, server) = tokio::io::duplex(1024);
+        tokio::spawn(handle_conn(server, state, FrameConfig::default()));
+        let client = crate::client::KvClient::new(client, 4);
+
+        let res = client.call(Request::new_rename("a", "b")).await.unwrap();
+        assert_eq!(res.status(), Ok(ResponseCode::NotFound));
+        client.put("a", "v1").await.unwrap();
+        client.put("b", "old").await.unwrap();
+        let res = client.call(Request::new_rename("a", "b")).await.unwrap();
+        assert_eq!(res.status(), Ok(ResponseCode::Ok));
+        assert_eq!(client.get("a").await.unwrap(), None);
+        assert_eq!(client.get("b").await.unwrap(), Some(b"v1".to_vec()));
+    }
+
+    #[test]
+    fn ttls_past_the_clock_are_rejected() {
+        let state = ServerState::new();
+        let err = state
+            .put("k".into(), vec![], Some(Duration::MAX))
+            .unwrap_err();
+        assert!(matches!(err, KvError::BadRequest(_)), "{:?}", err);
+        assert_eq!(state.get("k").unwrap(), None);
+        let stored = Stored::plain(vec![]);
+        let err = state
+            .default_db()
+            .insert("k".into(), stored, Some(Duration::MAX));
+        assert!(err.is_err());
+    }
+
+    #[tokio::test]
+    async fn put_ttl_expires() {
+        let state = Arc::new(ServerState::new());
+        let (client, server) = tokio::io::duplex(1024);
+        tokio::spawn(handle_conn(server, state, FrameConfig::default()));
+        let mut client = FrameConfig::default().framed(client);
+
+        let put = Request::new_put_ttl("hello", "world", Duration::from_millis(50));
+        client.send(put.into()).await.unwrap();
+        client.send(Request::new_get("hello").into()).await.unwrap();
+        let mut replies = client.map(|buf| Response::try_from(buf.unwrap()).unwrap());
+        assert_eq!(replies.next().await.unwrap().code, 0);
+        assert_eq!(replies.next().await.unwrap().code, 0);
+
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        replies
+            .get_mut()
+            .send(Request::new_get("hello").into())
+            .await
+            .unwrap();
+        assert_eq!(replies.next().await.unwrap().code, 404);
+    }
+
+    #[tokio::test]
+    async fn sweeper_purges_unread_keys() {
+        let clock = Arc::new(MockClock::new());
+        let state = Arc::new(ServerState::with_clock(clock.clone()));
+        state
+            .put(
+                "session".into(),
+                b"token".to_vec(),
+                Some(Duration::from_secs(60)),
+            )
+            .unwrap();
+        let mut expired = state.expired.subscribe();
+        let shutdown = CancellationToken::new();
+        let sweeper = spawn_sweeper(state.clone(), Duration::from_millis(5), shutdown.clone());
+
+        clock.advance(Duration::from_secs(61));
+        let key = tokio::time::timeout(Duration::from_secs(1), expired.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(key, (0, "session".to_owned()));
+        assert!(state.dbs[0].is_empty());
+
+        shutdown.cancel();
+        sweeper.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn sweeper_evicts_short_ttl_keys() {
+        let state = Arc::new(ServerState::new());
+        for i in 0..5 {
+            let key = format!("tmp:{}", i);
+            state
+                .put(key, vec![], Some(Duration::from_millis(20)))
+                .unwrap();
+        }
+        state.put("keep".into(), vec![], None).unwrap();
+        assert_eq!(state.dbs[0].len(), 6);
+
+        let shutdown = CancellationToken::new();
+        spawn_sweeper(state.clone(), Duration::from_millis(10), shutdown.clone());
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        shutdown.cancel();
+        assert_eq!(state.dbs[0].len(), 1);
+    }
+
+    #[tokio::test]
+    async fn expired_keys_are_streamed() {
+        let clock = Arc::new(MockClock::new());
+        let state = Arc::new(ServerState::with_clock(clock.clone()));
+        let (client, server) = tokio::io::duplex(1024);
+        tokio::spawn(handle_conn(server, state.clone(), FrameConfig::default()));
+        let mut client = FrameConfig::default().framed(client);
+
+        state
+            .put(
+                "session".into(),
+                b"token".to_vec(),
+                Some(Duration::from_millis(50)),
+            )
+            .unwrap();
+        state.put("user".into(), b"lxb".to_vec(), None).unwrap();
+        client
+            .send(Request::new_expired_subscribe().into())
+            .await
+            .unwrap();
+        let ack = Response::try_from(client.next().await.unwrap().unwrap()).unwrap();
+        assert_eq!(ack.code, 0);
+
+        assert_eq!(state.purge_expired(), 0);
+        clock.advance(Duration::from_millis(50));
+        assert_eq!(state.purge_expired(), 1);
+        let msg = Response::try_from(client.next().await.unwrap().unwrap()).unwrap();
+        assert_eq!(msg.key, "session");
+        assert_eq!(state.get("user").unwrap(), Some(b"lxb".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn subscribers_that_disconnect_are_dropped() {
+        let state = Arc::new(ServerState::new());
+        for req in [
+            Request::new_subscribe("a:"),
+            Request::new_expired_subscribe(),
+        ] {
+            let (client, server) = tokio::io::duplex(1024);
+            let conn = tokio::spawn(handle_conn(server, state.clone(), FrameConfig::default()));
+            let mut client = FrameConfig::default().framed(client);
+            client.send(req.into()).await.unwrap();
+            let ack = Response::try_from(client.next().await.unwrap().unwrap()).unwrap();
+            assert_eq!(ack.code, 0);
+
+            // no event ever comes, the connection still ends with the client
+            drop(client);
+            let served = timeout(Duration::from_secs(1), conn).await.unwrap();
+            assert!(served.unwrap().is_ok());
+        }
+        assert_eq!(state.changes.receiver_count(), 0);
+        assert_eq!(state.expired.receiver_count(), 0);
+    }
+
+    fn snapshot_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("kv-{}-{}.snap", name, std::process::id()))
+    }
+
+    #[test]
+    fn snapshot_roundtrip() {
+        let path = snapshot_path("roundtrip");
+        let clock = Arc::new(MockClock::new());
+        let state = ServerState::with_clock(clock.clone());
+        state.put("hello".into(), b"world".to_vec(), None).unwrap();
+        state
+            .put(
+                "session".into(),
+                b"token".to_vec(),
+                Some(Duration::from_secs(60)),
+            )
+            .unwrap();
+        state
+            .put("gone".into(), vec![], Some(Duration::from_secs(1)))
+            .unwrap();
+        clock.advance(Duration::from_secs(10));
+        assert_eq!(state.save_snapshot(&path).unwrap(), 2);
+
+        let clock = Arc::new(MockClock::new());
+        let loaded = ServerState::with_clock(clock.clone());
+        assert_eq!(loaded.load_snapshot(&path).unwrap(), 2);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.get("hello").unwrap(), Some(b"world".to_vec()));
+        assert_eq!(loaded.get("session").unwrap(), Some(b"token".to_vec()));
+        assert_eq!(loaded.get("gone").unwrap(), None);
+        clock.advance(Duration::from_secs(50));
+        assert_eq!(loaded.get("session").unwrap(), None);
+    }
+
+    #[test]
+    fn missing_snapshot_starts_empty() {
+        let state = ServerState::new();
+        let n = state.load_snapshot(snapshot_path("missing")).unwrap();
+        assert_eq!(n, 0);
+        assert!(state.scan("", usize::MAX).unwrap().is_empty());
+    }
+
+    #[test]
+    fn corrupt_snapshot_is_rejected() {
+        let path = snapshot_path("corrupt");
+        let state = ServerState::new();
+        state.put("hello".into(), b"world".to_vec(), None).unwrap();
+        state.save_snapshot(&path).unwrap();
+
+        let mut data = fs::read(&path).unwrap();
+        let i = data.len() / 2;
+        data[i] ^= 0x01;
+        fs::write(&path, &data).unwrap();
+        let err = ServerState::new().load_snapshot(&path).unwrap_err();
+        fs::remove_file(&path).unwrap();
+        assert!(err.to_string().contains("corrupt snapshot"), "{}", err);
+    }
+
+    #[test]
+    fn corrupt_compressed_values_fail_instead_of_panicking() {
+        let path = snapshot_path("corrupt-compressed");
+        let state = ServerState::new();
+        let text = "hello ".repeat(100);
+        state
+            .put_compressed("text".into(), text.into_bytes(), None)
+            .unwrap();
+        state.dbs[0].get_mut("text").unwrap().0.bytes = b"not zstd".to_vec();
+        assert!(state.get("text").is_err());
+        assert!(state.scan("", usize::MAX).is_err());
+
+        // the snapshot's checksum is fine, its compressed value isn't
+        state.save_snapshot(&path).unwrap();
+        let err = ServerState::new().load_snapshot(&path).unwrap_err();
+        fs::remove_file(&path).unwrap();
+        assert!(err.to_string().contains("corrupt snapshot"), "{}", err);
+    }
+
+    #[test]
+    fn wal_replay_recovers_writes() {
+        let wal = snapshot_path("wal");
+        let snapshot = snapshot_path("wal-snapshot");
+        let state = ServerState::new().with_wal(&wal).unwrap();
+        state.put("a".into(), b"1".to_vec(), None).unwrap();
+        state.incr("n".into(), 5).unwrap();
+        state.put("gone".into(), vec![], None).unwrap();
+        state.save_snapshot(&snapshot).unwrap();
+        // only the wal has the writes after the snapshot
+        state.incr("n".into(), 2).unwrap();
+        state.del("gone").unwrap();
+        state.cas("a".into(), b"1", b"2".to_vec()).unwrap();
+        let ttl = Some(Duration::from_secs(60));
+        state.put("b".into(), b"3".to_vec(), ttl).unwrap();
+        // a record cut short by a crash
+        let mut file = OpenOptions::new().append(true).open(&wal).unwrap();
+        file.write_all(&[10, 0x7f, b'x']).unwrap();
+
+        let dump = |state: &ServerState| -> Vec<_> {
+            let entries = state.scan("", usize::MAX).unwrap().into_iter();
+            entries.map(|e| (e.key, e.value)).collect()
+        };
+        let recovered = ServerState::new();
+        recovered.load_snapshot(&snapshot).unwrap();
+        assert_eq!(recovered.replay_wal(&wal).unwrap(), 7);
+        assert_eq!(dump(&recovered), dump(&state));
+
+        let from_wal = ServerState::new();
+        from_wal.replay_wal(&wal).unwrap();
+        fs::remove_file(&wal).unwrap();
+        fs::remove_file(&snapshot).unwrap();
+        assert_eq!(dump(&from_wal), dump(&state));
+    }
+
+    #[test]
+    fn replaying_the_wal_doesnt_append_to_it() {
+        let path = snapshot_path("replay-evict-wal");
+        let state = ServerState::new().with_wal(&path).unwrap();
+        for key in ["a", "b", "c"] {
+            state.put(key.into(), b"v".to_vec(), None).unwrap();
+        }
+        drop(state);
+        let len = fs::metadata(&path).unwrap().len();
+
+        // the wal is both replayed and written to, as on a restart
+        let state = ServerState::new().with_capacity(2).with_wal(&path).unwrap();
+        assert_eq!(state.replay_wal(&path).unwrap(), 3);
+        let logged = fs::metadata(&path).unwrap().len();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(logged, len);
+        assert_eq!(state.get("a").unwrap(), None);
+        assert_eq!(state.get("c").unwrap(), Some(b"v".to_vec()));
+    }
+
+    #[test]
+    fn downtime_counts_against_ttls_in_the_wal() {
+        let path = snapshot_path("ttl-wal");
+        let state = ServerState::new().with_wal(&path).unwrap();
+        state.put("a".into(), b"1".to_vec(), None).unwrap();
+        let ttl = Some(Duration::from_secs(60));
+        state.put("a".into(), b"2".to_vec(), ttl).unwrap();
+        state.put("b".into(), b"3".to_vec(), ttl).unwrap();
+
+        // restarted 30s later
+        let clock = Arc::new(MockClock::new());
+        clock.advance(Duration::from_secs(30));
+        let recovered = ServerState::with_clock(clock.clone());
+        assert_eq!(recovered.replay_wal(&path).unwrap(), 3);
+        assert_eq!(recovered.get("b").unwrap(), Some(b"3".to_vec()));
+        clock.advance(Duration::from_secs(31));
+        assert_eq!(recovered.get("b").unwrap(), None);
+
+        // restarted after the ttl, the expired put still replaces the older one
+        let clock = Arc::new(MockClock::new());
+        clock.advance(Duration::from_secs(61));
+        let recovered = ServerState::with_clock(clock);
+        assert_eq!(recovered.replay_wal(&path).unwrap(), 3);
+        fs::remove_file(&path).unwrap();
+        assert!(recovered.scan("", usize::MAX).unwrap().is_empty());
+    }
+
+    #[test]
+    fn databases_are_persisted() {
+        let wal = snapshot_path("db-wal");
+        let snapshot = snapshot_path("db-snapshot");
+        let state = ServerState::new().with_wal(&wal).unwrap();
+        state
+            .db(3)
+            .unwrap()
+            .put("a".into(), b"1".to_vec(), None)
+            .unwrap();
+        state.save_snapshot(&snapshot).unwrap();
+        state
+            .db(3)
+            .unwrap()
+            .put("b".into(), b"2".to_vec(), None)
+            .unwrap();
+
+        let recovered = ServerState::new();
+        recovered.load_snapshot(&snapshot).unwrap();
+        recovered.replay_wal(&wal).unwrap();
+        fs::remove_file(&wal).unwrap();
+        fs::remove_file(&snapshot).unwrap();
+        let db = recovered.db(3).unwrap();
+        assert_eq!(db.get("a").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(db.get("b").unwrap(), Some(b"2".to_vec()));
+        assert_eq!(recovered.get("a").unwrap(), None);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn a_failed_wal_refuses_writes() {
+        // every write to /dev/full fails
+        let state = ServerState::new().with_wal("/dev/full").unwrap();
+        assert!(state.put("a".into(), b"1".to_vec(), None).is_err());
+        assert!(state.put("b".into(), b"2".to_vec(), None).is_err());
+        assert_eq!(state.get("a").unwrap(), None);
+        assert_eq!(state.get("b").unwrap(), None);
+        assert!(state.truncate_wal().is_err());
+    }
+
+    #[test]
+    fn renames_are_replayed_from_the_wal() {
+        let path = snapshot_path("rename-wal");
+        let state = ServerState::new().with_wal(&path).unwrap();
+        state.put("a".into(), b"v1".to_vec(), None).unwrap();
+        state.rename("a", "b".into()).unwrap();
+        drop(state);
+
+        let state = ServerState::new();
+        state.replay_wal(&path).unwrap();
+        assert_eq!(state.get("a").unwrap(), None);
+        assert_eq!(state.get("b").unwrap(), Some(b"v1".to_vec()));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn renames_replay_over_a_snapshot_that_has_them() {
+        let wal = snapshot_path("rename-snapshot-wal");
+        let snapshot = snapshot_path("rename-snapshot");
+        let state = ServerState::new().with_wal(&wal).unwrap();
+        state.put("x".into(), b"5".to_vec(), None).unwrap();
+        state.truncate_wal().unwrap();
+        state.rename("x", "y".into()).unwrap();
+        state.put("x".into(), b"7".to_vec(), None).unwrap();
+        // a crash between saving the snapshot and truncating the wal
+        state.save_snapshot(&snapshot).unwrap();
+
+        let recovered = ServerState::new();
+        recovered.load_snapshot(&snapshot).unwrap();
+        recovered.replay_wal(&wal).unwrap();
+        fs::remove_file(&wal).unwrap();
+        fs::remove_file(&snapshot).unwrap();
+        assert_eq!(recovered.get("x").unwrap(), Some(b"7".to_vec()));
+        assert_eq!(recovered.get("y").unwrap(), Some(b"5".to_vec()));
+    }
+
+    #[test]
+    fn changes_are_published_in_the_order_applied() {
+        let state = Arc::new(ServerState::new());
+        let (_, mut changes) = state.default_db().subscribe("");
+        let threads: Vec<_> = (0..8)
+            .map(|i| {
+                let state = state.clone();
+                std::thread::spawn(move || {
+                    for j in 0..10 {
+                        let value = format!("{}-{}", i, j).into_bytes();
+                        state.put("k".into(), value, None).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for t in threads {
+            t.join().unwrap();
+        }
+        let mut last = None;
+        while let Ok((_, event)) = changes.try_recv() {
+            last = Some(event.value);
+        }
+        assert_eq!(last, state.get("k").unwrap());
+    }
+
+    #[tokio::test]
+    async fn subscribers_get_replayed_changes() {
+        let state = Arc::new(ServerState::new().with_replay_size(2));
+        state.put("a:1".into(), b"1".to_vec(), None).unwrap();
+        state.put("b:1".into(), b"1".to_vec(), None).unwrap();
+        state.put("a:2".into(), b"2".to_vec(), None).unwrap();
+        state.put("a:3".into(), b"3".to_vec(), None).unwrap();
+        state.del("a:2").unwrap();
+
+        let (client, server) = tokio::io::duplex(1024);
+        tokio::spawn(handle_conn(server, state.clone(), FrameConfig::default()));
+        let mut client = FrameConfig::default().framed(client);
+        client
+            .send(Request::new_subscribe("a:").into())
+            .await
+            .unwrap();
+        let mut events = client.map(|buf| Response::try_from(buf.unwrap()).unwrap());
+        assert_eq!(events.next().await.unwrap().code, 0);
+
+        // only the last two changes are kept
+        assert_eq!(
+            events.next().await.unwrap(),
+            Response::new("a:3".into(), b"3".to_vec())
+        );
+        assert_eq!(
+            events.next().await.unwrap(),
+            Response::not_found("a:2".into())
+        );
+
+        state.put("b:2".into(), b"2".to_vec(), None).unwrap();
+        state.put("a:4".into(), b"4".to_vec(), None).unwrap();
+        assert_eq!(
+            events.next().await.unwrap(),
+            Response::new("a:4".into(), b"4".to_vec())
+        );
+    }
+
+    #[tokio::test]
+    async fn watches_stream_only_new_changes_and_survive_lagging() {
+        let state = Arc::new(ServerState::new().with_replay_size(2));
+        state.put("a:0".into(), b"0".to_vec(), None).unwrap();
+
+        let (client, server) = tokio::io::duplex(1024);
+        tokio::spawn(handle_conn(server, state.clone(), FrameConfig::default()));
+        let mut client = FrameConfig::default().framed(client);
+        client.send(Request::new_watch("a:").into()).await.unwrap();
+        let mut events = client.map(|buf| Response::try_from(buf.unwrap()).unwrap());
+        assert_eq!(events.next().await.unwrap().code, 0);
+
+        // more changes than the channel holds, made before the watch reads any
+        let total = CHANGE_CHANNEL_SIZE * 2;
+        for i in 1..=total {
+            state.put(format!("a:{}", i), vec![], None).unwrap();
+        }
+        let last = format!("a:{}", total);
+        let mut received = vec![];
+        while received.last() != Some(&last) {
+            received.push(events.next().await.unwrap().key);
+        }
+        // the buffered a:0 isn't replayed, the oldest changes are dropped
+        assert_eq!(received.len(), CHANGE_CHANNEL_SIZE);
+        assert_eq!(
+            received[0],
+            format!("a:{}", total - CHANGE_CHANNEL_SIZE + 1)
+        );
+
+        // and the watch goes on
+        state.put("a:next".into(), b"1".to_vec(), None).unwrap();
+        assert_eq!(
+            events.next().await.unwrap(),
+            Response::new("a:next".into(), b"1".to_vec())
+        );
+    }
+
+    #[tokio::test]
+    async fn scan_command_lists_prefixed_keys() {
+        let state = Arc::new(ServerState::new());
+        state.put("user:2".into(), b"b".to_vec(), None).unwrap();
+        state.put("post:1".into(), b"p".to_vec(), None).unwrap();
+        state.put("user:1".into(), b"a".to_vec(), None).unwrap();
+        let (client, server) = tokio::io::duplex(1024);
+        tokio::spawn(handle_conn(server, state, FrameConfig::default()));
+        let mut client = FrameConfig::default().framed(client);
+
+        client
+            .send(Request::new_scan("user:", 0).into())
+            .await
+            .unwrap();
+        client
+            .send(Request::new_scan("user:", 1).into())
+            .await
+            .unwrap();
+        let mut replies = client.map(|buf| Response::try_from(buf.unwrap()).unwrap());
+        let res = replies.next().await.unwrap();
+        assert_eq!(res.status(), Ok(ResponseCode::Ok));
+        assert_eq!(
+            res.entries,
+            [
+                Response::new("user:1".into(), b"a".to_vec()),
+                Response::new("user:2".into(), b"b".to_vec()),
+            ]
+        );
+        let res = replies.next().await.unwrap();
+        assert_eq!(res.entries.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn mget_keeps_request_order() {
+        let state = Arc::new(ServerState::new());
+        state.put("a".into(), b"1".to_vec(), None).unwrap();
+        state.put("c".into(), b"3".to_vec(), None).unwrap();
+        let (client, server) = tokio::io::duplex(1024);
+        tokio::spawn(handle_conn(server, state, FrameConfig::default()));
+        let mut client = FrameConfig::default().framed(client);
+
+        let req = Request::new_mget(&["c", "b", "a", "c"]);
+        client.send(req.into()).await.unwrap();
+        client.send(Request::new_mget(&[]).into()).await.unwrap();
+        let mut replies = client.map(|buf| Response::try_from(buf.unwrap()).unwrap());
+        let res = replies.next().await.unwrap();
+        assert_eq!(
+            res.entries,
+            [
+                Response::new("c".into(), b"3".to_vec()),
+                Response::not_found("b".into()),
+                Response::new("a".into(), b"1".to_vec()),
+                Response::new("c".into(), b"3".to_vec()),
+            ]
+        );
+        let res = replies.next().await.unwrap();
+        assert_eq!(res.status(), Ok(ResponseCode::Ok));
+        assert!(res.entries.is_empty());
+    }
+
+    #[test]
+    fn scan_is_sorted_across_shards() {
+        let state = ServerState::new();
+        let mut ids: Vec<u32> = (0..200).collect();
+        // shuffle deterministically
+        ids.sort_by_key(|i| (i * 7919) % 211);
+        for i in &ids {
+            state
+                .put(format!("user:{:03}", i), i.to_le_bytes().to_vec(), None)
+                .unwrap();
+            state.put(format!("post:{:03}", i), vec![], None).unwrap();
+        }
+
+        let keys: Vec<_> = state
+            .scan("user:", 500)
+            .unwrap()
+            .into_iter()
+            .map(|e| e.key)
+            .collect();
+        let expected: Vec<_> = (0..200).map(|i| format!("user:{:03}", i)).collect();
+        assert_eq!(keys, expected);
+
+        let entries = state.scan("user:", 3).unwrap();
+        let keys: Vec<_> = entries.iter().map(|e| e.key.as_str()).collect();
+        assert_eq!(keys, ["user:000", "user:001", "user:002"]);
+        assert_eq!(entries[2].value, 2u32.to_le_bytes());
+        assert!(state.scan("none:", 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn range_scan_filters_on_the_numeric_suffix() {
+        let state = ServerState::new();
+        for key in ["ts:10", "ts:20", "ts:30", "ts:x", "other:20"] {
+            state.put(key.into(), vec![], None).unwrap();
+        }
+        let all = usize::MAX;
+        assert_eq!(state.range_scan("ts:", 15, 25, all), ["ts:20"]);
+        assert_eq!(
+            state.range_scan("ts:", 0, 100, all),
+            ["ts:10", "ts:20", "ts:30"]
+        );
+        assert!(state.range_scan("ts:", 31, 40, all).is_empty());
+        assert_eq!(state.range_scan("ts:", 0, 100, 2), ["ts:10", "ts:20"]);
+
+        state.put("ts:-5".into(), vec![], None).unwrap();
+        assert_eq!(state.range_scan("ts:", -10, 10, all), ["ts:-5", "ts:10"]);
+    }
+
+    #[test]
+    fn range_scan_reads_hyphens_as_signs_only_after_the_prefix() {
+        let state = ServerState::new();
+        for key in ["user-42", "day:2024-01-15", "n:-3"] {
+            state.put(key.into(), vec![], None).unwrap();
+        }
+        assert_eq!(state.range_scan("user-", 0, 100, usize::MAX), ["user-42"]);
+        assert_eq!(state.range_scan("", 40, 50, usize::MAX), ["user-42"]);
+        assert_eq!(
+            state.range_scan("day:", 1, 31, usize::MAX),
+            ["day:2024-01-15"]
+        );
+        assert_eq!(state.range_scan("n:", -5, 0, usize::MAX), ["n:-3"]);
+        assert_eq!(
+            state.range_scan("", -5, 0, usize::MAX),
+            Vec::<String>::new()
+        );
     }
 }
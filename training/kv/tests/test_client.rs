@@ -0,0 +1,227 @@
+use std::{sync::Arc, time::Duration};
+
+use futures::StreamExt;
+use kv::{
+    client::{self, Backoff, KvClient},
+    pb::{FrameConfig, Request, Response},
+    server::{self, ServerState},
+};
+use tokio::{
+    net::TcpListener,
+    time::{sleep, timeout},
+};
+use tokio_util::sync::CancellationToken;
+
+#[tokio::test]
+async fn client_against_server() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let state = Arc::new(ServerState::new());
+    let shutdown = CancellationToken::new();
+    tokio::spawn(server::serve(
+        listener,
+        state,
+        FrameConfig::default(),
+        true,
+        shutdown,
+    ));
+
+    let client = KvClient::connect(addr, 4).await.unwrap();
+    assert_eq!(client.get("hello").await.unwrap(), None);
+    client.put("hello", "world").await.unwrap();
+    assert_eq!(client.get("hello").await.unwrap(), Some(b"world".to_vec()));
+    assert!(client.del("hello").await.unwrap());
+    assert!(!client.del("hello").await.unwrap());
+    assert_eq!(client.get("hello").await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn shutdown_drains_open_connections() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let state = Arc::new(ServerState::new());
+    let shutdown = CancellationToken::new();
+    let server = tokio::spawn(server::serve(
+        listener,
+        state,
+        FrameConfig::default(),
+        true,
+        shutdown.clone(),
+    ));
+
+    let client = KvClient::connect(addr, 4).await.unwrap();
+    client.put("hello", "world").await.unwrap();
+    shutdown.cancel();
+
+    // no new connections, the open one is still served
+    assert!(KvClient::connect(addr, 4).await.is_err());
+    assert_eq!(client.get("hello").await.unwrap(), Some(b"world".to_vec()));
+    drop(client);
+
+    let served = timeout(Duration::from_secs(1), server).await.unwrap();
+    served.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn changes_are_watched() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let state = Arc::new(ServerState::new());
+    let shutdown = CancellationToken::new();
+    tokio::spawn(server::serve(
+        listener,
+        state,
+        FrameConfig::default(),
+        true,
+        shutdown,
+    ));
+
+    let events = client::watch(addr, "a:").await.unwrap();
+    tokio::spawn(async move {
+        let client = KvClient::connect(addr, 4).await.unwrap();
+        client.put("b:1", "skipped").await.unwrap();
+        client.put("a:1", "1").await.unwrap();
+        client.del("a:1").await.unwrap();
+    });
+
+    let events: Vec<_> = events.take(2).map(Result::unwrap).collect().await;
+    assert_eq!(
+        events,
+        [
+            Response::new("a:1".into(), b"1".to_vec()),
+            Response::not_found("a:1".into())
+        ]
+    );
+}
+
+#[tokio::test]
+async fn connections_beyond_the_limit_wait() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let state = Arc::new(ServerState::new().with_max_conns(2));
+    let shutdown = CancellationToken::new();
+    tokio::spawn(server::serve(
+        listener,
+        state,
+        FrameConfig::default(),
+        true,
+        shutdown,
+    ));
+
+    let first = KvClient::connect(addr, 4).await.unwrap();
+    let second = KvClient::connect(addr, 4).await.unwrap();
+    first.put("hello", "world").await.unwrap();
+    second.get("hello").await.unwrap();
+
+    // connected, but not served until a slot frees up
+    let third = KvClient::connect(addr, 4).await.unwrap();
+    let mut get = tokio::spawn(async move { third.get("hello").await });
+    assert!(timeout(Duration::from_millis(100), &mut get).await.is_err());
+
+    drop(first);
+    let value = timeout(Duration::from_secs(1), get).await.unwrap();
+    assert_eq!(value.unwrap().unwrap(), Some(b"world".to_vec()));
+}
+
+// serves a single connection, aborting it kills the server
+async fn serve_one(listener: TcpListener, state: Arc<ServerState>) -> anyhow::Result<()> {
+    let (stream, _) = listener.accept().await?;
+    server::handle_conn(stream, state, FrameConfig::default()).await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn client_reconnects_after_server_restart() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let state = Arc::new(ServerState::new());
+    let first = tokio::spawn(serve_one(listener, state.clone()));
+
+    let client = KvClient::connect(addr, 4)
+        .await
+        .unwrap()
+        .with_backoff(Backoff {
+            initial: Duration::from_millis(20),
+            max: Duration::from_millis(100),
+            max_retries: 10,
+        });
+    client.put("hello", "world").await.unwrap();
+    first.abort();
+    assert!(first.await.is_err());
+
+    // the server comes back while the client is re-dialing
+    tokio::spawn(async move {
+        sleep(Duration::from_millis(50)).await;
+        let listener = TcpListener::bind(addr).await.unwrap();
+        serve_one(listener, state).await
+    });
+    assert_eq!(client.get("hello").await.unwrap(), Some(b"world".to_vec()));
+    assert!(!client.is_closed());
+}
+
+#[tokio::test]
+async fn client_reselects_its_database_after_reconnecting() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let state = Arc::new(ServerState::new());
+    let first = tokio::spawn(serve_one(listener, state.clone()));
+
+    let client = KvClient::connect(addr, 4)
+        .await
+        .unwrap()
+        .with_backoff(Backoff {
+            initial: Duration::from_millis(20),
+            max: Duration::from_millis(100),
+            max_retries: 10,
+        });
+    client.select(3).await.unwrap();
+    client.put("hello", "world").await.unwrap();
+    first.abort();
+    assert!(first.await.is_err());
+
+    let listener = TcpListener::bind(addr).await.unwrap();
+    tokio::spawn(serve_one(listener, state.clone()));
+    assert_eq!(client.get("hello").await.unwrap(), Some(b"world".to_vec()));
+    client.put("again", "1").await.unwrap();
+    assert_eq!(
+        state.db(3).unwrap().get("again").unwrap(),
+        Some(b"1".to_vec())
+    );
+    assert_eq!(state.get("again").unwrap(), None);
+}
+
+#[tokio::test]
+async fn writes_are_not_resent_after_a_lost_reply() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let state = Arc::new(ServerState::new());
+    // applies the first request, then closes the connection without replying
+    let first = {
+        let state = state.clone();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = FrameConfig::default().framed(stream);
+            let buf = stream.next().await.unwrap().unwrap();
+            state.apply(Request::try_from(buf).unwrap());
+            listener
+        })
+    };
+
+    let client = KvClient::connect(addr, 4)
+        .await
+        .unwrap()
+        .with_backoff(Backoff {
+            initial: Duration::from_millis(20),
+            max: Duration::from_millis(100),
+            max_retries: 10,
+        });
+    let incr = client.call(Request::new_incr("n", 1));
+    assert!(timeout(Duration::from_secs(1), incr)
+        .await
+        .unwrap()
+        .is_err());
+    let listener = first.await.unwrap();
+    tokio::spawn(serve_one(listener, state));
+    let n = client.get("n").await.unwrap().unwrap();
+    assert_eq!(i64::from_le_bytes(n.try_into().unwrap()), 1);
+}
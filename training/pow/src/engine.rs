@@ -0,0 +1,125 @@
+use sha2::{Digest, Sha256};
+use tokio::sync::mpsc;
+
+use crate::pb::Block;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MiningProgress {
+    /// nonces tried so far
+    pub attempts: u64,
+    /// most leading zero bits seen so far
+    pub best_zeros: u32,
+}
+
+/// Sends a `MiningProgress` every `every` attempts. Reports are dropped
+/// rather than stalling the miner when the receiver falls behind.
+pub struct ProgressReporter {
+    tx: mpsc::Sender<MiningProgress>,
+    every: u64,
+}
+
+impl ProgressReporter {
+    pub fn new(tx: mpsc::Sender<MiningProgress>, every: u64) -> Self {
+        Self {
+            tx,
+            every: every.max(1),
+        }
+    }
+}
+
+pub fn hash(data: &[u8], nonce: u32) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update(data);
+    hasher.update(nonce.to_le_bytes());
+    hasher.finalize().to_vec()
+}
+
+/// Identifies a block by its data, independent of the nonce.
+pub fn block_id(data: &[u8]) -> Vec<u8> {
+    Sha256::digest(data).to_vec()
+}
+
+pub fn leading_zeros(hash: &[u8]) -> u32 {
+    let mut zeros = 0;
+    for b in hash {
+        zeros += b.leading_zeros();
+        if *b != 0 {
+            break;
+        }
+    }
+    zeros
+}
+
+/// Tries nonces `0..=max_nonce` until `sha256(data ++ nonce)` has at least
+/// `difficulty` leading zero bits, returns the block with `hash` and `nonce`
+/// filled in, or `None` if no nonce in range works.
+pub fn mine_bounded(
+    block: &Block,
+    difficulty: u32,
+    max_nonce: u32,
+    progress: Option<&ProgressReporter>,
+) -> Option<Block> {
+    let mut best_zeros = 0;
+    for nonce in 0..=max_nonce {
+        let hash = hash(&block.data, nonce);
+        let zeros = leading_zeros(&hash);
+        best_zeros = best_zeros.max(zeros);
+
+        let attempts = nonce as u64 + 1;
+        if let Some(p) = progress {
+            if attempts.is_multiple_of(p.every) {
+                let _ = p.tx.try_send(MiningProgress {
+                    attempts,
+                    best_zeros,
+                });
+            }
+        }
+
+        if zeros >= difficulty {
+            return Some(Block {
+                data: block.data.clone(),
+                hash,
+                nonce,
+            });
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block() -> Block {
+        Block {
+            data: b"hello world".to_vec(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn it_works() {
+        let mined = mine_bounded(&block(), 8, u32::MAX, None).unwrap();
+        assert!(leading_zeros(&mined.hash) >= 8);
+        assert_eq!(mined.hash, hash(&mined.data, mined.nonce));
+        assert_eq!(leading_zeros(&[0, 0x10, 0]), 11);
+        assert!(mine_bounded(&block(), 256, 10, None).is_none());
+    }
+
+    #[tokio::test]
+    async fn progress_is_reported() {
+        let (tx, mut rx) = mpsc::channel(64);
+        let reporter = ProgressReporter::new(tx, 1);
+        let mined = mine_bounded(&block(), 1, u32::MAX, Some(&reporter)).unwrap();
+        drop(reporter);
+
+        let mut reports = vec![];
+        while let Some(p) = rx.recv().await {
+            reports.push(p);
+        }
+        assert!(!reports.is_empty());
+        let last = reports.last().unwrap();
+        assert_eq!(last.attempts, mined.nonce as u64 + 1);
+        assert!(last.best_zeros >= 1);
+    }
+}
@@ -11,13 +11,13 @@ pub struct Block {
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct BlockStatus {
-    ///
+    /// 0 means ok
     #[prost(uint32, tag="1")]
     pub code: u32,
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct ClientInfo {
-    ///
+    /// client name, used as the subscription key
     #[prost(string, tag="1")]
     pub name: ::prost::alloc::string::String,
 }
@@ -131,6 +131,24 @@ pub mod pow_builder_client {
             let path = http::uri::PathAndQuery::from_static("/abi.PowBuilder/Submit");
             self.inner.unary(request.into_request(), path, codec).await
         }
+        /// stop accepting subscriptions, notify subscribers and exit
+        pub async fn shutdown(
+            &mut self,
+            request: impl tonic::IntoRequest<super::ClientInfo>,
+        ) -> Result<tonic::Response<super::BlockStatus>, tonic::Status> {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/abi.PowBuilder/Shutdown");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
     }
 }
 /// Generated server implementations.
@@ -154,6 +172,11 @@ pub mod pow_builder_server {
             &self,
             request: tonic::Request<super::Block>,
         ) -> Result<tonic::Response<super::BlockStatus>, tonic::Status>;
+        /// stop accepting subscriptions, notify subscribers and exit
+        async fn shutdown(
+            &self,
+            request: tonic::Request<super::ClientInfo>,
+        ) -> Result<tonic::Response<super::BlockStatus>, tonic::Status>;
     }
     #[derive(Debug)]
     pub struct PowBuilderServer<T: PowBuilder> {
@@ -277,6 +300,42 @@ pub mod pow_builder_server {
                     };
                     Box::pin(fut)
                 }
+                "/abi.PowBuilder/Shutdown" => {
+                    #[allow(non_camel_case_types)]
+                    struct ShutdownSvc<T: PowBuilder>(pub Arc<T>);
+                    impl<T: PowBuilder> tonic::server::UnaryService<super::ClientInfo>
+                    for ShutdownSvc<T> {
+                        type Response = super::BlockStatus;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::ClientInfo>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).shutdown(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = ShutdownSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
                 _ => {
                     Box::pin(async move {
                         Ok(
@@ -1,18 +1,98 @@
+mod engine;
 mod pb;
-use std::{collections::HashMap, pin::Pin, sync::Arc};
+use std::{collections::HashMap, env, pin::Pin, sync::Arc, time::Duration};
 
+use anyhow::Result;
+use engine::{block_id, mine_bounded, ProgressReporter};
 use pb::{pow_builder_server::*, *};
-use tokio::sync::{mpsc, RwLock};
-use tonic::{codegen::futures_core::Stream, Status};
+use tokio::{
+    sync::{
+        mpsc::{self, error::TrySendError},
+        RwLock,
+    },
+    task::JoinHandle,
+    time::timeout,
+};
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_util::sync::CancellationToken;
+use tonic::{codegen::futures_core::Stream, transport::Server, Status};
 
+const BLOCK_QUEUE_SIZE: usize = 32;
+const CLIENT_QUEUE_SIZE: usize = 8;
+const DIFFICULTY: u32 = 20;
+const PROGRESS_EVERY: u64 = 1 << 16;
+// how long a full subscriber queue may hold up its final notification
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(1);
+// metadata key carrying the token that authorizes a shutdown
+const ADMIN_TOKEN_KEY: &str = "x-admin-token";
+
+#[derive(Default)]
 struct Shared {
     clients: HashMap<String, mpsc::Sender<Result<BlockHash, Status>>>,
+    // set once the subscribers are closed, later subscriptions are refused
+    closed: bool,
 }
 
 pub struct PowService {
     //send block to PoW engine
     tx: mpsc::Sender<Block>,
     shared: Arc<RwLock<Shared>>,
+    shutdown: CancellationToken,
+    // shutdown is refused unless the request carries this token
+    admin_token: Option<String>,
+}
+
+impl PowService {
+    fn new(tx: mpsc::Sender<Block>, shutdown: CancellationToken) -> Self {
+        Self {
+            tx,
+            shared: Arc::new(RwLock::new(Shared::default())),
+            shutdown,
+            admin_token: None,
+        }
+    }
+
+    fn with_admin_token(mut self, token: impl Into<String>) -> Self {
+        self.admin_token = Some(token.into());
+        self
+    }
+
+    fn is_admin<T>(&self, request: &tonic::Request<T>) -> bool {
+        let token = request
+            .metadata()
+            .get(ADMIN_TOKEN_KEY)
+            .and_then(|v| v.to_str().ok());
+        matches!((&self.admin_token, token), (Some(want), Some(got)) if want == got)
+    }
+}
+
+fn shutting_down() -> Status {
+    Status::unavailable("server is shutting down")
+}
+
+// refuse later subscriptions and send a terminal status to every subscriber,
+// waiting up to CLOSE_TIMEOUT for room in a full queue; dropping the senders
+// then closes their streams
+async fn close_subscribers(shared: &RwLock<Shared>) {
+    let clients: Vec<_> = {
+        let mut shared = shared.write().await;
+        shared.closed = true;
+        shared.clients.drain().collect()
+    };
+    let closing: Vec<_> = clients
+        .into_iter()
+        .map(|(name, sender)| {
+            tokio::spawn(async move {
+                match timeout(CLOSE_TIMEOUT, sender.send(Err(shutting_down()))).await {
+                    Ok(_) => println!("subscriber {} closed", name),
+                    Err(_) => println!("subscriber {} closed, final notification timed out", name),
+                }
+            })
+        })
+        .collect();
+    for task in closing {
+        let _ = task.await;
+    }
 }
 
 #[tonic::async_trait]
@@ -23,17 +103,318 @@ impl PowBuilder for PowService {
         &self,
         request: tonic::Request<ClientInfo>,
     ) -> Result<tonic::Response<Self::SubscribeStream>, Status> {
-        todo!()
+        if self.shutdown.is_cancelled() {
+            return Err(shutting_down());
+        }
+        let name = request.into_inner().name;
+        let (sender, receiver) = mpsc::channel(CLIENT_QUEUE_SIZE);
+        let mut shared = self.shared.write().await;
+        // checked under the lock, the subscribers may have been closed since
+        if shared.closed {
+            return Err(shutting_down());
+        }
+        shared.clients.insert(name, sender);
+        Ok(tonic::Response::new(Box::pin(ReceiverStream::new(
+            receiver,
+        ))))
     }
 
+    // queues the block for the engine, the hash comes later through subscribe
     async fn submit(
         &self,
         request: tonic::Request<Block>,
     ) -> Result<tonic::Response<BlockStatus>, Status> {
-        todo!()
+        if self.shutdown.is_cancelled() {
+            return Err(shutting_down());
+        }
+        self.tx
+            .send(request.into_inner())
+            .await
+            .map_err(|_| Status::unavailable("pow engine is gone"))?;
+        Ok(tonic::Response::new(BlockStatus { code: 0 }))
+    }
+
+    // the engine drains the queued blocks, broadcasts their hashes and only
+    // then closes the subscribers
+    async fn shutdown(
+        &self,
+        request: tonic::Request<ClientInfo>,
+    ) -> Result<tonic::Response<BlockStatus>, Status> {
+        if !self.is_admin(&request) {
+            return Err(Status::permission_denied("shutdown needs the admin token"));
+        }
+        println!("shutdown requested by {}", request.into_inner().name);
+        self.shutdown.cancel();
+        Ok(tonic::Response::new(BlockStatus { code: 0 }))
+    }
+}
+
+// PoW engine, mines each block to `difficulty` leading zero bits and sends
+// the hash to every subscriber; once `shutdown` is cancelled, or every
+// sender is gone, it stops taking blocks, drains the queue and closes the
+// subscribers
+fn spawn_pow_engine(
+    mut rx: mpsc::Receiver<Block>,
+    shared: Arc<RwLock<Shared>>,
+    difficulty: u32,
+    shutdown: CancellationToken,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            let block = tokio::select! {
+                block = rx.recv() => block,
+                _ = shutdown.cancelled() => break,
+            };
+            match block {
+                Some(block) => mine_and_broadcast(&shared, block, difficulty).await,
+                None => break,
+            }
+        }
+        rx.close();
+        while let Some(block) = rx.recv().await {
+            mine_and_broadcast(&shared, block, difficulty).await;
+        }
+        close_subscribers(&shared).await;
+    })
+}
+
+async fn mine_and_broadcast(shared: &RwLock<Shared>, block: Block, difficulty: u32) {
+    if let Some(block) = mine(block, difficulty).await {
+        broadcast(shared, block).await;
+    }
+}
+
+async fn mine(block: Block, difficulty: u32) -> Option<Block> {
+    let (tx, mut progress) = mpsc::channel(16);
+    let reporter = ProgressReporter::new(tx, PROGRESS_EVERY);
+    let miner = tokio::task::spawn_blocking(move || {
+        mine_bounded(&block, difficulty, u32::MAX, Some(&reporter))
+    });
+    // ends once the miner is done and the reporter dropped
+    while let Some(p) = progress.recv().await {
+        println!(
+            "mining: {} nonces tried, best {} zeros",
+            p.attempts, p.best_zeros
+        );
+    }
+    match miner.await {
+        Ok(Some(block)) => {
+            println!("block mined, nonce={}", block.nonce);
+            Some(block)
+        }
+        Ok(None) => {
+            println!("no nonce found");
+            None
+        }
+        Err(e) => {
+            println!("miner failed: {}", e);
+            None
+        }
+    }
+}
+
+// never waits on a subscriber: those that went away, or whose queue is
+// full, are dropped and their streams end
+async fn broadcast(shared: &RwLock<Shared>, block: Block) {
+    let hash = BlockHash {
+        id: block_id(&block.data),
+        hash: block.hash,
+    };
+    let mut dropped = Vec::new();
+    for (name, sender) in shared.read().await.clients.iter() {
+        match sender.try_send(Ok(hash.clone())) {
+            Ok(()) => continue,
+            Err(TrySendError::Full(_)) => println!("subscriber {} lagging, dropped", name),
+            Err(TrySendError::Closed(_)) => println!("subscriber {} gone", name),
+        }
+        dropped.push((name.clone(), sender.clone()));
+    }
+    if dropped.is_empty() {
+        return;
+    }
+    let clients = &mut shared.write().await.clients;
+    for (name, sender) in dropped {
+        // the name may belong to a new subscriber by now
+        if clients.get(&name).is_some_and(|s| s.same_channel(&sender)) {
+            clients.remove(&name);
+        }
     }
 }
 
-fn main() {
-    println!("Hello, world!");
+#[tokio::main]
+async fn main() -> Result<()> {
+    let addr = "0.0.0.0:8889".parse()?;
+    let shutdown = CancellationToken::new();
+    let (tx, rx) = mpsc::channel(BLOCK_QUEUE_SIZE);
+    let mut svc = PowService::new(tx, shutdown.clone());
+    // POW_ADMIN_TOKEN: secret the shutdown rpc must carry, shutdown is
+    // refused without it
+    if let Ok(token) = env::var("POW_ADMIN_TOKEN") {
+        svc = svc.with_admin_token(token);
+    }
+    let engine = spawn_pow_engine(rx, svc.shared.clone(), DIFFICULTY, shutdown.clone());
+
+    println!("Listening on {}", addr);
+    Server::builder()
+        .add_service(PowBuilderServer::new(svc))
+        .serve_with_shutdown(addr, shutdown.cancelled())
+        .await?;
+
+    // the engine has drained and closed the subscribers, or serving would
+    // still wait on their streams
+    engine.await?;
+    println!("bye");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio_stream::StreamExt;
+
+    fn client(name: &str) -> tonic::Request<ClientInfo> {
+        tonic::Request::new(ClientInfo { name: name.into() })
+    }
+
+    #[tokio::test]
+    async fn submitted_blocks_reach_the_engine() {
+        let (tx, mut rx) = mpsc::channel(1);
+        let svc = PowService::new(tx, CancellationToken::new());
+        let block = Block {
+            data: b"hello world".to_vec(),
+            ..Default::default()
+        };
+
+        let status = svc.submit(tonic::Request::new(block.clone())).await;
+        assert_eq!(status.unwrap().into_inner().code, 0);
+        assert_eq!(rx.recv().await, Some(block.clone()));
+
+        drop(rx);
+        let status = svc.submit(tonic::Request::new(block)).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::Unavailable);
+    }
+
+    #[tokio::test]
+    async fn mined_hashes_reach_subscribers() {
+        let (tx, rx) = mpsc::channel(1);
+        let svc = PowService::new(tx, CancellationToken::new());
+        let engine = spawn_pow_engine(rx, svc.shared.clone(), 1, CancellationToken::new());
+        let mut stream = svc.subscribe(client("lxb")).await.unwrap().into_inner();
+
+        let block = Block {
+            data: b"hello world".to_vec(),
+            ..Default::default()
+        };
+        svc.submit(tonic::Request::new(block)).await.unwrap();
+        let mined = stream.next().await.unwrap().unwrap();
+        assert_eq!(mined.id, block_id(b"hello world"));
+        assert!(engine::leading_zeros(&mined.hash) >= 1);
+
+        drop(svc);
+        engine.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn lagging_subscribers_are_dropped() {
+        let (tx, _rx) = mpsc::channel(1);
+        let svc = PowService::new(tx, CancellationToken::new());
+        let mut slow = svc.subscribe(client("slow")).await.unwrap().into_inner();
+        let mut fast = svc.subscribe(client("fast")).await.unwrap().into_inner();
+
+        for nonce in 0..=CLIENT_QUEUE_SIZE as u32 {
+            let block = Block {
+                nonce,
+                ..Default::default()
+            };
+            broadcast(&svc.shared, block).await;
+            assert!(fast.next().await.unwrap().is_ok());
+        }
+        let clients = &svc.shared.read().await.clients;
+        assert_eq!(clients.keys().collect::<Vec<_>>(), ["fast"]);
+
+        // what was queued is still delivered, then the stream ends
+        for _ in 0..CLIENT_QUEUE_SIZE {
+            assert!(slow.next().await.unwrap().is_ok());
+        }
+        assert!(slow.next().await.is_none());
+    }
+
+    fn admin(name: &str, token: &str) -> tonic::Request<ClientInfo> {
+        let mut request = client(name);
+        request
+            .metadata_mut()
+            .insert(ADMIN_TOKEN_KEY, token.parse().unwrap());
+        request
+    }
+
+    #[tokio::test]
+    async fn shutdown_closes_subscribers() {
+        let (tx, rx) = mpsc::channel(1);
+        let token = CancellationToken::new();
+        let svc = PowService::new(tx, token.clone()).with_admin_token("secret");
+        let engine = spawn_pow_engine(rx, svc.shared.clone(), 1, token.clone());
+
+        let mut stream = svc.subscribe(client("lxb")).await.unwrap().into_inner();
+        let block = Block {
+            data: b"hello world".to_vec(),
+            ..Default::default()
+        };
+        svc.submit(tonic::Request::new(block)).await.unwrap();
+        let status = svc.shutdown(admin("admin", "secret")).await.unwrap();
+        assert_eq!(status.into_inner().code, 0);
+        assert!(token.is_cancelled());
+        assert!(svc.subscribe(client("late")).await.is_err());
+
+        // the queued block is still mined and broadcast before the close
+        engine.await.unwrap();
+        assert!(svc.shared.read().await.clients.is_empty());
+        let mined = stream.next().await.unwrap().unwrap();
+        assert_eq!(mined.id, block_id(b"hello world"));
+        let status = stream.next().await.unwrap().unwrap_err();
+        assert_eq!(status.code(), tonic::Code::Unavailable);
+        assert!(stream.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn shutdown_needs_the_admin_token() {
+        let (tx, _rx) = mpsc::channel(1);
+        let token = CancellationToken::new();
+        let svc = PowService::new(tx, token.clone());
+        let status = svc.shutdown(admin("admin", "secret")).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::PermissionDenied);
+
+        let svc = svc.with_admin_token("secret");
+        for request in [client("admin"), admin("admin", "guess")] {
+            let status = svc.shutdown(request).await.unwrap_err();
+            assert_eq!(status.code(), tonic::Code::PermissionDenied);
+        }
+        assert!(!token.is_cancelled());
+    }
+
+    #[tokio::test]
+    async fn full_subscribers_still_get_the_final_notification() {
+        let (tx, _rx) = mpsc::channel(1);
+        let svc = PowService::new(tx, CancellationToken::new());
+        let mut stream = svc.subscribe(client("slow")).await.unwrap().into_inner();
+        for nonce in 0..CLIENT_QUEUE_SIZE as u32 {
+            let block = Block {
+                nonce,
+                ..Default::default()
+            };
+            broadcast(&svc.shared, block).await;
+        }
+
+        let shared = svc.shared.clone();
+        let closing = tokio::spawn(async move { close_subscribers(&shared).await });
+        for _ in 0..CLIENT_QUEUE_SIZE {
+            assert!(stream.next().await.unwrap().is_ok());
+        }
+        let status = stream.next().await.unwrap().unwrap_err();
+        assert_eq!(status.code(), tonic::Code::Unavailable);
+        assert!(stream.next().await.is_none());
+        closing.await.unwrap();
+
+        // a subscription racing the close is refused, not left open
+        assert!(svc.subscribe(client("late")).await.is_err());
+    }
 }
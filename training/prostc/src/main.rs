@@ -1,30 +1,59 @@
-use std::{env, path};
+use std::{env, path, process};
+
+use anyhow::{bail, Context, Result};
+use walkdir::WalkDir;
 
 fn main() {
     let args: Vec<_> = env::args().collect();
+    if let Err(e) = run(&args) {
+        eprintln!("error: {:#}", e);
+        process::exit(1);
+    }
+}
+
+/// `prostc [--serde] <in_dir> <out_dir>`
+///
+/// With `--serde` every generated message also derives `serde::Serialize`
+/// and `serde::Deserialize`, the crate using the generated code then needs
+/// `serde` (with the `derive` feature) as a dependency.
+fn run(args: &[String]) -> Result<()> {
+    let serde = args.iter().skip(1).any(|arg| arg == "--serde");
+    let args: Vec<_> = args.iter().filter(|arg| *arg != "--serde").collect();
     if args.len() != 3 {
-        println!("{} <in_dir> <out_dir>", args[0]);
-        return;
+        let bin = args.first().map_or("prostc", |arg| arg.as_str());
+        bail!("usage: {} [--serde] <in_dir> <out_dir>", bin);
     }
     let in_dir = path::Path::new(args[1].as_str());
-    if !in_dir.exists() || !in_dir.is_dir() {
-        println!("invalid in_dir {:?}", in_dir);
-        return;
+    if !in_dir.is_dir() {
+        bail!("invalid in_dir {:?}", in_dir);
     }
     let out_dir = path::Path::new(args[2].as_str());
-    if !out_dir.exists() || !out_dir.is_dir() {
-        println!("invalid out_dir {:?}", out_dir);
-        return;
-    }
-    let protos_pat = in_dir.join("*.proto");
-    let protos = protos_pat.to_str().unwrap();
-    env::set_var("OUT_DIR", out_dir.to_str().unwrap());
-    prost_build::compile_protos(&[protos], &[args[1].as_str()]).unwrap();
+    if !out_dir.is_dir() {
+        bail!("invalid out_dir {:?}", out_dir);
+    }
+    // every .proto under in_dir, imports are resolved from in_dir
+    let mut protos = Vec::new();
+    for entry in WalkDir::new(in_dir).sort_by_file_name() {
+        let entry = entry.with_context(|| format!("failed to read {:?}", in_dir))?;
+        let path = entry.path();
+        if entry.file_type().is_file() && path.extension().is_some_and(|ext| ext == "proto") {
+            protos.push(path.to_path_buf());
+        }
+    }
+    let mut config = prost_build::Config::new();
+    if serde {
+        config.type_attribute(".", "#[derive(serde::Serialize, serde::Deserialize)]");
+    }
+    config
+        .out_dir(out_dir)
+        .compile_protos(&protos, &[in_dir])
+        .with_context(|| format!("failed to compile protos in {:?}", in_dir))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::fs;
 
     #[test]
     fn it_works() {
@@ -33,4 +62,84 @@ mod tests {
         assert!(p.exists());
         assert!(p.is_dir());
     }
+
+    // a fresh scratch directory, removed by the caller
+    fn temp_dir(name: &str) -> path::PathBuf {
+        let dir = env::temp_dir().join(format!("prostc-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn args(in_dir: &path::Path, out_dir: &path::Path) -> Vec<String> {
+        vec![
+            "prostc".to_string(),
+            in_dir.to_str().unwrap().to_string(),
+            out_dir.to_str().unwrap().to_string(),
+        ]
+    }
+
+    #[test]
+    fn serde_flag_derives_serde() {
+        let dir = temp_dir("serde");
+        fs::write(
+            dir.join("a.proto"),
+            "syntax = \"proto3\";\npackage a;\nmessage A { uint32 id = 1; }",
+        )
+        .unwrap();
+        let mut plain = args(&dir, &dir);
+        run(&plain).unwrap();
+        let without = fs::read_to_string(dir.join("a.rs")).unwrap();
+        plain.insert(1, "--serde".to_string());
+        let res = run(&plain);
+        let with = fs::read_to_string(dir.join("a.rs"));
+        fs::remove_dir_all(&dir).unwrap();
+        res.unwrap();
+        let derive = "#[derive(serde::Serialize, serde::Deserialize)]";
+        assert!(!without.contains(derive));
+        assert!(with.unwrap().contains(derive));
+    }
+
+    #[test]
+    fn missing_args_print_usage() {
+        let err = run(&[]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "usage: prostc [--serde] <in_dir> <out_dir>"
+        );
+    }
+
+    #[test]
+    fn invalid_proto_is_an_error() {
+        let dir = temp_dir("invalid");
+        fs::write(dir.join("bad.proto"), "syntax = \"proto3\";\nmessage {").unwrap();
+        let res = run(&args(&dir, &dir));
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn protos_in_subdirectories_are_compiled() {
+        let in_dir = temp_dir("nested-in");
+        let out_dir = temp_dir("nested-out");
+        fs::create_dir(in_dir.join("sub")).unwrap();
+        fs::write(
+            in_dir.join("a.proto"),
+            "syntax = \"proto3\";\npackage a;\nmessage A { uint32 id = 1; }",
+        )
+        .unwrap();
+        fs::write(
+            in_dir.join("sub/b.proto"),
+            "syntax = \"proto3\";\npackage b;\nmessage B { string name = 1; }",
+        )
+        .unwrap();
+        let res = run(&args(&in_dir, &out_dir));
+        let a = out_dir.join("a.rs").exists();
+        let b = out_dir.join("b.rs").exists();
+        fs::remove_dir_all(&in_dir).unwrap();
+        fs::remove_dir_all(&out_dir).unwrap();
+        res.unwrap();
+        assert!(a);
+        assert!(b);
+    }
 }